@@ -0,0 +1,193 @@
+use {
+    criterion::{criterion_group, BatchSize, Criterion, Throughput},
+    csv::StringRecord,
+    engine::{
+        account::Account,
+        engine::Engine,
+        event::{Event, EventData},
+        generate::{self, Generator, GeneratorConfig},
+        store::StorageKind,
+        Amount, ClientId,
+    },
+    rust_decimal::Decimal,
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        convert::TryFrom,
+        io,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+};
+
+const EVENTS: usize = 100_000;
+
+/// Counts the bytes currently allocated, to measure the memory used by a data structure.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn events(config: GeneratorConfig) -> Vec<Event> {
+    Generator::new(config).unwrap().collect()
+}
+
+fn deposit_heavy() -> Vec<Event> {
+    events(GeneratorConfig {
+        events: EVENTS,
+        clients: 1000,
+        dispute_rate: 0.0,
+        withdrawal_rate: 0.1,
+        ..GeneratorConfig::default()
+    })
+}
+
+fn dispute_heavy() -> Vec<Event> {
+    events(GeneratorConfig {
+        events: EVENTS,
+        clients: 1000,
+        dispute_rate: 0.9,
+        chargeback_rate: 0.2,
+        ..GeneratorConfig::default()
+    })
+}
+
+fn parse(c: &mut Criterion) {
+    let mut csv = Vec::new();
+    generate::write_events(&mut csv, deposit_heavy()).unwrap();
+    let records: Vec<StringRecord> = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(csv.as_slice())
+        .records()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(records.len() as u64));
+    group.bench_function("Event::try_from", |b| {
+        b.iter_batched(
+            || records.clone(),
+            |records| {
+                for record in records {
+                    Event::try_from(record).unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn read_events(c: &mut Criterion) {
+    let mut csv = Vec::new();
+    generate::write_events(&mut csv, deposit_heavy()).unwrap();
+
+    let mut group = c.benchmark_group("read_events");
+    group.throughput(Throughput::Elements(EVENTS as u64));
+    group.bench_function("generated", |b| {
+        b.iter_batched(
+            Engine::new,
+            |mut engine| {
+                engine.read_events(csv.as_slice()).unwrap();
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn handle_event(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_event");
+    group.throughput(Throughput::Elements(EVENTS as u64));
+    for (name, events) in [
+        ("deposit heavy", deposit_heavy()),
+        ("dispute heavy", dispute_heavy()),
+    ] {
+        for storage in [StorageKind::Map, StorageKind::Dense] {
+            group.bench_function(format!("{}/{}", name, storage), |b| {
+                b.iter_batched(
+                    || Engine::new().with_storage(storage),
+                    |mut engine| {
+                        for event in &events {
+                            let _ = engine.handle_event(*event);
+                        }
+                        engine
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn write_accounts_state(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_accounts_state");
+    group.throughput(Throughput::Elements(1 << 16));
+    for storage in [StorageKind::Map, StorageKind::Dense] {
+        let mut engine = Engine::new().with_storage(storage);
+        for client in 0..=u16::MAX {
+            engine
+                .handle_event(Event {
+                    client: ClientId::from(client),
+                    data: EventData::Deposit {
+                        transaction_id: u32::from(client).into(),
+                        amount: Amount::from(Decimal::new(i64::from(client) + 1, 4)),
+                    },
+                })
+                .unwrap();
+        }
+        group.bench_function(format!("65536 clients/{}", storage), |b| {
+            b.iter(|| engine.write_accounts_state(io::sink()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Reports the heap memory used per deposit kept in an account's deposit history.
+fn deposit_history_memory() {
+    const DEPOSITS: u32 = 1_000_000;
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let mut account = Account::new();
+    for transaction_id in 0..DEPOSITS {
+        account
+            .deposit(transaction_id.into(), Amount::from(Decimal::new(1, 4)))
+            .unwrap();
+    }
+    let used = ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(account);
+
+    println!(
+        "DepositHistory: {:.1} bytes per deposit over {} deposits",
+        used as f64 / f64::from(DEPOSITS),
+        DEPOSITS
+    );
+}
+
+criterion_group!(
+    benches,
+    parse,
+    read_events,
+    handle_event,
+    write_accounts_state
+);
+
+fn main() {
+    deposit_history_memory();
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+}
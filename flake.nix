@@ -16,7 +16,6 @@
             cargo-watch
             rustfmt
             clippy
-            python3
           ];
         };
       });
@@ -0,0 +1,108 @@
+#![no_main]
+
+use {
+    arbitrary::Arbitrary,
+    engine::{
+        clearing::ClearingPolicy,
+        engine::Engine,
+        event::{Event, EventData},
+        risk::{RiskAction, RiskRule, RiskRules},
+    },
+    libfuzzer_sys::fuzz_target,
+    rust_decimal::Decimal,
+    std::io,
+};
+
+/// Few enough clients and transactions that events often refer to earlier ones.
+const CLIENTS: u16 = 8;
+const TRANSACTIONS: u32 = 64;
+
+/// Deposits stay pending for a few events, so that some are cleared explicitly and some are
+/// disputed or withdrawn from before they clear.
+const PENDING_EVENTS: u64 = 4;
+
+/// Accounts lock after this many disputes within `DISPUTE_EVENTS` events, or on going negative.
+const DISPUTES: usize = 3;
+const DISPUTE_EVENTS: u64 = 16;
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    WithdrawalRequest,
+    WithdrawalSettle,
+    WithdrawalCancel,
+    Clear,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    kind: Kind,
+    client: u16,
+    transaction: u32,
+    /// The parts of any `Decimal`, including huge ones and ones with 28 decimal places, which the
+    /// CSV parser would also accept.
+    amount: (u32, u32, u32, bool, u8),
+}
+
+impl From<Input> for Event {
+    fn from(input: Input) -> Self {
+        let transaction_id = (input.transaction % TRANSACTIONS).into();
+        let (lo, mid, hi, negative, scale) = input.amount;
+        let amount = Decimal::from_parts(lo, mid, hi, negative, u32::from(scale) % 29).into();
+        let data = match input.kind {
+            Kind::Deposit => EventData::Deposit {
+                transaction_id,
+                amount,
+            },
+            Kind::Withdrawal => EventData::Withdrawal {
+                transaction_id,
+                amount,
+            },
+            Kind::Dispute => EventData::Dispute { transaction_id },
+            Kind::Resolve => EventData::Resolve { transaction_id },
+            Kind::Chargeback => EventData::Chargeback { transaction_id },
+            Kind::WithdrawalRequest => EventData::WithdrawalRequest {
+                transaction_id,
+                amount,
+            },
+            Kind::WithdrawalSettle => EventData::WithdrawalSettle { transaction_id },
+            Kind::WithdrawalCancel => EventData::WithdrawalCancel { transaction_id },
+            Kind::Clear => EventData::Clear { transaction_id },
+        };
+        Self {
+            client: (input.client % CLIENTS).into(),
+            data,
+        }
+    }
+}
+
+// Events may be rejected, but must never cause a panic, and every account and the system as a
+// whole must stay consistent after each one.
+fuzz_target!(|inputs: Vec<Input>| {
+    let mut engine = Engine::new()
+        .with_clearing(ClearingPolicy::AfterEvents(PENDING_EVENTS))
+        .with_risk_rules(
+            RiskRules::default()
+                .with_rule(RiskRule::NegativeBalance, RiskAction::Freeze)
+                .with_rule(
+                    RiskRule::Disputes {
+                        count: DISPUTES,
+                        events: DISPUTE_EVENTS,
+                    },
+                    RiskAction::Lock,
+                ),
+        )
+        .with_audit();
+    for input in inputs {
+        let event = Event::from(input);
+        let _ = engine.handle_event(event);
+        if let Err(error) = engine.audit() {
+            panic!("Invariant violated after {:?}: {}", event, error);
+        }
+    }
+    engine.write_accounts_state(io::sink()).unwrap();
+});
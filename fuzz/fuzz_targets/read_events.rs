@@ -0,0 +1,33 @@
+#![no_main]
+
+use {
+    engine::engine::{Engine, EngineError},
+    libfuzzer_sys::fuzz_target,
+    std::io,
+};
+
+/// The error itself, without the location it happened at.
+fn cause(error: &EngineError) -> &EngineError {
+    match error {
+        EngineError::AtLocation { error, .. } => cause(error),
+        error => error,
+    }
+}
+
+// Arbitrary bytes may be rejected as input, but must never cause a panic or leave the engine in
+// a state that breaks its invariants.
+fuzz_target!(|data: &[u8]| {
+    let mut engine = Engine::new().with_audit();
+    if let Err(error) = engine.read_events(data) {
+        assert!(
+            !matches!(
+                cause(&error),
+                EngineError::InvariantViolation { .. } | EngineError::AuditError(_)
+            ),
+            "{}",
+            error
+        );
+    }
+    engine.audit().unwrap();
+    engine.write_accounts_state(io::sink()).unwrap();
+});
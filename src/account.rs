@@ -1,5 +1,11 @@
 use {
-    crate::{Amount, TransactionId},
+    crate::{
+        limits::{AccountLimits, Limits},
+        risk::DisputeActivity,
+        window::Window,
+        Amount, Moment, TransactionId,
+    },
+    derive_more::Display,
     std::collections::HashMap,
     thiserror::Error,
 };
@@ -10,24 +16,67 @@ pub enum DepositError {
     DuplicateTransactionId(TransactionId),
     #[error("Account is locked")]
     AccountLocked,
+    #[error("Balance would exceed the limit of {0}")]
+    ExceedsMaxBalance(Amount),
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
 }
 
 #[derive(Debug, Error)]
 pub enum WithdrawError {
+    #[error("Transaction ID {0} has already been used")]
+    DuplicateTransactionId(TransactionId),
     #[error("Account is locked")]
     AccountLocked,
+    #[error("Account is frozen")]
+    AccountFrozen,
+    #[error("Withdrawal exceeds the limit of {0}")]
+    ExceedsMaxWithdrawal(Amount),
+    #[error("Withdrawals would exceed the limit of {0} within {1}")]
+    ExceedsMaxWithdrawn(Amount, Window),
     #[error("Insufficient funds")]
     InsufficientFunds,
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
+}
+
+/// Error settling or cancelling a requested withdrawal.
+#[derive(Debug, Error)]
+pub enum WithdrawalStateError {
+    #[error("Withdrawal does not exist")]
+    WithdrawalDoesNotExist,
+    #[error("Withdrawal has already been settled")]
+    WithdrawalAlreadySettled,
+    #[error("Withdrawal has already been cancelled")]
+    WithdrawalAlreadyCancelled,
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
+}
+
+#[derive(Debug, Error)]
+pub enum ClearError {
+    #[error("Deposit does not exist")]
+    DepositDoesNotExist,
+    #[error("Deposit has already cleared")]
+    DepositAlreadyCleared,
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
 }
 
 #[derive(Debug, Error)]
 pub enum DisputeError {
     #[error("Deposit does not exist")]
     DepositDoesNotExist,
+    #[error("Deposit has not cleared yet")]
+    DepositPending,
+    #[error("Account already has the most open disputes allowed, {0}")]
+    TooManyOpenDisputes(usize),
     #[error("Deposit is already disputed")]
     DepositAlreadyDisputed,
     #[error("Deposit has already been reversed")]
     DepositAlreadyReversed,
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
 }
 
 #[derive(Debug, Error)]
@@ -38,6 +87,8 @@ pub enum ResolveError {
     DepositNotDisputed,
     #[error("Deposit has already been reversed")]
     DepositAlreadyReversed,
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
 }
 
 #[derive(Debug, Error)]
@@ -48,6 +99,8 @@ pub enum ChargebackError {
     DepositNotDisputed,
     #[error("Deposit has already been reversed")]
     DepositAlreadyReversed,
+    #[error("Funds would exceed the largest representable amount")]
+    Overflow,
 }
 
 #[derive(Debug, Error)]
@@ -62,28 +115,127 @@ pub enum AccountError {
     Resolve(#[from] ResolveError),
     #[error("Chargeback error: {0}")]
     Chargeback(#[from] ChargebackError),
+    #[error("Withdrawal state error: {0}")]
+    WithdrawalState(#[from] WithdrawalStateError),
+    #[error("Clear error: {0}")]
+    Clear(#[from] ClearError),
 }
 
-#[derive(Debug, Clone, Copy)]
-enum DepositState {
+impl AccountError {
+    /// Name of the specific error variant, for example `"Withdraw::InsufficientFunds"`.
+    pub fn variant(&self) -> &'static str {
+        match self {
+            Self::Deposit(DepositError::DuplicateTransactionId(_)) => {
+                "Deposit::DuplicateTransactionId"
+            }
+            Self::Deposit(DepositError::AccountLocked) => "Deposit::AccountLocked",
+            Self::Deposit(DepositError::ExceedsMaxBalance(_)) => "Deposit::ExceedsMaxBalance",
+            Self::Deposit(DepositError::Overflow) => "Deposit::Overflow",
+            Self::Withdraw(WithdrawError::DuplicateTransactionId(_)) => {
+                "Withdraw::DuplicateTransactionId"
+            }
+            Self::Withdraw(WithdrawError::AccountLocked) => "Withdraw::AccountLocked",
+            Self::Withdraw(WithdrawError::AccountFrozen) => "Withdraw::AccountFrozen",
+            Self::Withdraw(WithdrawError::ExceedsMaxWithdrawal(_)) => {
+                "Withdraw::ExceedsMaxWithdrawal"
+            }
+            Self::Withdraw(WithdrawError::ExceedsMaxWithdrawn(..)) => {
+                "Withdraw::ExceedsMaxWithdrawn"
+            }
+            Self::Withdraw(WithdrawError::InsufficientFunds) => "Withdraw::InsufficientFunds",
+            Self::Withdraw(WithdrawError::Overflow) => "Withdraw::Overflow",
+            Self::Dispute(DisputeError::DepositDoesNotExist) => "Dispute::DepositDoesNotExist",
+            Self::Dispute(DisputeError::DepositPending) => "Dispute::DepositPending",
+            Self::Dispute(DisputeError::TooManyOpenDisputes(_)) => "Dispute::TooManyOpenDisputes",
+            Self::Dispute(DisputeError::DepositAlreadyDisputed) => {
+                "Dispute::DepositAlreadyDisputed"
+            }
+            Self::Dispute(DisputeError::DepositAlreadyReversed) => {
+                "Dispute::DepositAlreadyReversed"
+            }
+            Self::Dispute(DisputeError::Overflow) => "Dispute::Overflow",
+            Self::Resolve(ResolveError::DepositDoesNotExist) => "Resolve::DepositDoesNotExist",
+            Self::Resolve(ResolveError::DepositNotDisputed) => "Resolve::DepositNotDisputed",
+            Self::Resolve(ResolveError::DepositAlreadyReversed) => {
+                "Resolve::DepositAlreadyReversed"
+            }
+            Self::Resolve(ResolveError::Overflow) => "Resolve::Overflow",
+            Self::Chargeback(ChargebackError::DepositDoesNotExist) => {
+                "Chargeback::DepositDoesNotExist"
+            }
+            Self::Chargeback(ChargebackError::DepositNotDisputed) => {
+                "Chargeback::DepositNotDisputed"
+            }
+            Self::Chargeback(ChargebackError::DepositAlreadyReversed) => {
+                "Chargeback::DepositAlreadyReversed"
+            }
+            Self::Chargeback(ChargebackError::Overflow) => "Chargeback::Overflow",
+            Self::WithdrawalState(WithdrawalStateError::WithdrawalDoesNotExist) => {
+                "WithdrawalState::WithdrawalDoesNotExist"
+            }
+            Self::WithdrawalState(WithdrawalStateError::WithdrawalAlreadySettled) => {
+                "WithdrawalState::WithdrawalAlreadySettled"
+            }
+            Self::WithdrawalState(WithdrawalStateError::WithdrawalAlreadyCancelled) => {
+                "WithdrawalState::WithdrawalAlreadyCancelled"
+            }
+            Self::WithdrawalState(WithdrawalStateError::Overflow) => "WithdrawalState::Overflow",
+            Self::Clear(ClearError::DepositDoesNotExist) => "Clear::DepositDoesNotExist",
+            Self::Clear(ClearError::DepositAlreadyCleared) => "Clear::DepositAlreadyCleared",
+            Self::Clear(ClearError::Overflow) => "Clear::Overflow",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepositState {
+    /// The deposit hasn't cleared yet, so its amount is pending and it can't be disputed.
+    Pending,
+    /// The deposit may still be disputed, but currently isn't.
     MaybeSettled,
+    /// The deposit is disputed and its amount is held.
     Disputed,
+    /// The deposit was charged back. This is a final state.
     Reversed,
 }
 
+/// Read-only view of a deposit that has been applied to an account.
 #[derive(Debug, Clone, Copy)]
-struct ProcessedDeposit {
+pub struct ProcessedDeposit {
     state: DepositState,
+    /// Whether the deposit was ever disputed, even if the dispute was resolved since.
+    disputed: bool,
     amount: Amount,
 }
 
 impl ProcessedDeposit {
-    fn new(amount: Amount) -> Self {
+    fn new(state: DepositState, amount: Amount) -> Self {
         Self {
-            state: DepositState::MaybeSettled,
+            state,
+            disputed: false,
             amount,
         }
     }
+
+    pub fn state(&self) -> DepositState {
+        self.state
+    }
+
+    pub fn was_disputed(&self) -> bool {
+        self.disputed
+    }
+
+    pub(crate) fn restored(state: DepositState, disputed: bool, amount: Amount) -> Self {
+        Self {
+            state,
+            disputed,
+            amount,
+        }
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
 }
 
 /// Thin wrapper around `std::collections::HashMap` that manages the finite state machines for a
@@ -97,24 +249,79 @@ impl DepositHistory {
     fn insert(
         &mut self,
         transaction_id: TransactionId,
-        amount: Amount,
+        deposit: ProcessedDeposit,
     ) -> Result<(), DepositError> {
         if self.inner.contains_key(&transaction_id) {
             return Err(DepositError::DuplicateTransactionId(transaction_id));
         }
-        self.inner
-            .insert(transaction_id, ProcessedDeposit::new(amount));
+        self.inner.insert(transaction_id, deposit);
         Ok(())
     }
 
+    fn get(&self, transaction_id: TransactionId) -> Option<&ProcessedDeposit> {
+        self.inner.get(&transaction_id)
+    }
+
+    /// Puts a deposit back to exactly how it was, including removing it if it didn't exist.
+    fn restore(&mut self, transaction_id: TransactionId, deposit: Option<ProcessedDeposit>) {
+        match deposit {
+            Some(deposit) => self.inner.insert(transaction_id, deposit),
+            None => self.inner.remove(&transaction_id),
+        };
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (TransactionId, &ProcessedDeposit)> {
+        self.inner
+            .iter()
+            .map(|(transaction_id, deposit)| (*transaction_id, deposit))
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn count(&self, state: DepositState) -> usize {
+        self.inner
+            .values()
+            .filter(|deposit| deposit.state == state)
+            .count()
+    }
+
+    fn sum(&self, state: DepositState) -> Amount {
+        self.inner
+            .values()
+            .filter(|deposit| deposit.state == state)
+            .fold(Amount::default(), |sum, deposit| {
+                sum.saturating_add(deposit.amount)
+            })
+    }
+
+    fn clear(&mut self, transaction_id: TransactionId) -> Result<&Amount, ClearError> {
+        let deposit = self
+            .inner
+            .get_mut(&transaction_id)
+            .ok_or(ClearError::DepositDoesNotExist)?;
+        match deposit.state {
+            DepositState::Pending => {
+                deposit.state = DepositState::MaybeSettled;
+                Ok(&deposit.amount)
+            }
+            DepositState::MaybeSettled | DepositState::Disputed | DepositState::Reversed => {
+                Err(ClearError::DepositAlreadyCleared)
+            }
+        }
+    }
+
     fn dispute(&mut self, transaction_id: TransactionId) -> Result<&Amount, DisputeError> {
         let deposit = self
             .inner
             .get_mut(&transaction_id)
             .ok_or(DisputeError::DepositDoesNotExist)?;
         match deposit.state {
+            DepositState::Pending => Err(DisputeError::DepositPending),
             DepositState::MaybeSettled => {
                 deposit.state = DepositState::Disputed;
+                deposit.disputed = true;
                 Ok(&deposit.amount)
             }
             DepositState::Disputed => Err(DisputeError::DepositAlreadyDisputed),
@@ -128,7 +335,9 @@ impl DepositHistory {
             .get_mut(&transaction_id)
             .ok_or(ResolveError::DepositDoesNotExist)?;
         match deposit.state {
-            DepositState::MaybeSettled => Err(ResolveError::DepositNotDisputed),
+            DepositState::Pending | DepositState::MaybeSettled => {
+                Err(ResolveError::DepositNotDisputed)
+            }
             DepositState::Disputed => {
                 deposit.state = DepositState::MaybeSettled;
                 Ok(&deposit.amount)
@@ -143,7 +352,9 @@ impl DepositHistory {
             .get_mut(&transaction_id)
             .ok_or(ChargebackError::DepositDoesNotExist)?;
         match deposit.state {
-            DepositState::MaybeSettled => Err(ChargebackError::DepositNotDisputed),
+            DepositState::Pending | DepositState::MaybeSettled => {
+                Err(ChargebackError::DepositNotDisputed)
+            }
             DepositState::Disputed => {
                 deposit.state = DepositState::Reversed;
                 Ok(&deposit.amount)
@@ -153,12 +364,256 @@ impl DepositHistory {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalState {
+    /// The withdrawal was requested and its amount is reserved until it is settled or cancelled.
+    Reserved,
+    /// The funds were paid out. This is a final state.
+    Settled,
+    /// The funds were returned to the available funds. This is a final state.
+    Cancelled,
+}
+
+/// Read-only view of a withdrawal that was requested from an account.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestedWithdrawal {
+    state: WithdrawalState,
+    amount: Amount,
+}
+
+impl RequestedWithdrawal {
+    pub(crate) fn restored(state: WithdrawalState, amount: Amount) -> Self {
+        Self { state, amount }
+    }
+
+    pub fn state(&self) -> WithdrawalState {
+        self.state
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+}
+
+/// Manages the finite state machines for a collection of two-phase withdrawals, the same way
+/// `DepositHistory` does for deposits.
+#[derive(Debug, Default, Clone)]
+struct WithdrawalHistory {
+    inner: HashMap<TransactionId, RequestedWithdrawal>,
+}
+
+impl WithdrawalHistory {
+    fn insert(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), WithdrawError> {
+        if self.inner.contains_key(&transaction_id) {
+            return Err(WithdrawError::DuplicateTransactionId(transaction_id));
+        }
+        let withdrawal = RequestedWithdrawal {
+            state: WithdrawalState::Reserved,
+            amount,
+        };
+        self.inner.insert(transaction_id, withdrawal);
+        Ok(())
+    }
+
+    fn get(&self, transaction_id: TransactionId) -> Option<&RequestedWithdrawal> {
+        self.inner.get(&transaction_id)
+    }
+
+    /// Puts a withdrawal back to exactly how it was, including removing it if it didn't exist.
+    fn restore(&mut self, transaction_id: TransactionId, withdrawal: Option<RequestedWithdrawal>) {
+        match withdrawal {
+            Some(withdrawal) => self.inner.insert(transaction_id, withdrawal),
+            None => self.inner.remove(&transaction_id),
+        };
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (TransactionId, &RequestedWithdrawal)> {
+        self.inner
+            .iter()
+            .map(|(transaction_id, withdrawal)| (*transaction_id, withdrawal))
+    }
+
+    fn sum(&self, state: WithdrawalState) -> Amount {
+        self.inner
+            .values()
+            .filter(|withdrawal| withdrawal.state == state)
+            .fold(Amount::default(), |sum, withdrawal| {
+                sum.saturating_add(withdrawal.amount)
+            })
+    }
+
+    fn settle(&mut self, transaction_id: TransactionId) -> Result<&Amount, WithdrawalStateError> {
+        let withdrawal = self
+            .inner
+            .get_mut(&transaction_id)
+            .ok_or(WithdrawalStateError::WithdrawalDoesNotExist)?;
+        match withdrawal.state {
+            WithdrawalState::Reserved => {
+                withdrawal.state = WithdrawalState::Settled;
+                Ok(&withdrawal.amount)
+            }
+            WithdrawalState::Settled => Err(WithdrawalStateError::WithdrawalAlreadySettled),
+            WithdrawalState::Cancelled => Err(WithdrawalStateError::WithdrawalAlreadyCancelled),
+        }
+    }
+
+    fn cancel(&mut self, transaction_id: TransactionId) -> Result<&Amount, WithdrawalStateError> {
+        let withdrawal = self
+            .inner
+            .get_mut(&transaction_id)
+            .ok_or(WithdrawalStateError::WithdrawalDoesNotExist)?;
+        match withdrawal.state {
+            WithdrawalState::Reserved => {
+                withdrawal.state = WithdrawalState::Cancelled;
+                Ok(&withdrawal.amount)
+            }
+            WithdrawalState::Settled => Err(WithdrawalStateError::WithdrawalAlreadySettled),
+            WithdrawalState::Cancelled => Err(WithdrawalStateError::WithdrawalAlreadyCancelled),
+        }
+    }
+}
+
+/// Changes to each kind of funds of an account, applied all at once by `Account::move_funds`.
+#[derive(Debug, Default, Clone, Copy)]
+struct Transfer {
+    available: Amount,
+    held: Amount,
+    reserved: Amount,
+    pending: Amount,
+}
+
+/// What kind of thing locked an account.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum LockCause {
+    #[display(fmt = "chargeback")]
+    Chargeback,
+    /// The account was locked directly, with `Engine::lock_account` or `Account::set_lock`.
+    #[display(fmt = "admin")]
+    Admin,
+    /// A risk rule was broken.
+    #[display(fmt = "rule")]
+    Rule,
+}
+
+/// Why an account was locked.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    /// A deposit was charged back.
+    #[display(fmt = "chargeback")]
+    Chargeback,
+    /// The account was locked directly.
+    #[display(fmt = "admin")]
+    Admin,
+    /// Too many disputes were opened within a window, as per `RiskRule::Disputes`.
+    #[display(fmt = "disputes")]
+    Disputes,
+    /// Too large a share of deposits was disputed, as per `RiskRule::DisputeRatio`.
+    #[display(fmt = "dispute_ratio")]
+    DisputeRatio,
+    /// The available funds became negative, as per `RiskRule::NegativeBalance`.
+    #[display(fmt = "negative_balance")]
+    NegativeBalance,
+}
+
+impl LockReason {
+    pub fn cause(self) -> LockCause {
+        match self {
+            Self::Chargeback => LockCause::Chargeback,
+            Self::Admin => LockCause::Admin,
+            Self::Disputes | Self::DisputeRatio | Self::NegativeBalance => LockCause::Rule,
+        }
+    }
+}
+
+/// How much a lock restricts an account.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    /// Funds can't leave the account, but it still takes deposits and disputes.
+    #[display(fmt = "frozen")]
+    Frozen,
+    /// The account takes no more deposits or withdrawals.
+    #[display(fmt = "locked")]
+    Locked,
+}
+
+/// How and when an account was locked or frozen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lock {
+    pub reason: LockReason,
+    pub level: LockLevel,
+    /// The transaction of the event that locked the account, if an event did.
+    pub transaction_id: Option<TransactionId>,
+    /// Number of events the engine had handled when the account was locked, including the one
+    /// that locked it. Only known for accounts locked within an engine.
+    pub sequence: Option<u64>,
+}
+
+impl Lock {
+    pub fn new(reason: LockReason) -> Self {
+        Self {
+            reason,
+            level: LockLevel::Locked,
+            transaction_id: None,
+            sequence: None,
+        }
+    }
+
+    pub fn with_level(mut self, level: LockLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_transaction_id(mut self, transaction_id: TransactionId) -> Self {
+        self.transaction_id = Some(transaction_id);
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    pub fn cause(&self) -> LockCause {
+        self.reason.cause()
+    }
+}
+
+/// The parts of an account that a single operation on a transaction can change, so that the
+/// operation can be undone.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Checkpoint {
+    lock: Option<Lock>,
+    available_funds: Amount,
+    held_funds: Amount,
+    reserved_funds: Amount,
+    pending_funds: Amount,
+    transaction_id: TransactionId,
+    deposit: Option<ProcessedDeposit>,
+    withdrawal: Option<RequestedWithdrawal>,
+    withdrawals_recorded: u64,
+    disputes_recorded: u64,
+    disputed_deposits: usize,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Account {
-    locked: bool,
+    /// Boxed, since few accounts are ever locked.
+    lock: Option<Box<Lock>>,
     available_funds: Amount,
     held_funds: Amount,
+    reserved_funds: Amount,
+    pending_funds: Amount,
     deposit_history: DepositHistory,
+    withdrawal_history: WithdrawalHistory,
+    /// Boxed, since most accounts have no limits and a dense store holds an account for every
+    /// possible client.
+    limits: Option<Box<AccountLimits>>,
+    /// Only kept when risk rules count disputes, boxed for the same reason as `limits`.
+    dispute_activity: Option<Box<DisputeActivity>>,
 }
 
 impl Account {
@@ -166,8 +621,86 @@ impl Account {
         Self::default()
     }
 
+    /// Replaces the limits of the account. Withdrawals made while it had no rolling limit don't
+    /// count towards a new one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        match &mut self.limits {
+            _ if limits.is_empty() => self.limits = None,
+            Some(account_limits) => account_limits.set(limits),
+            None => self.limits = Some(Box::new(AccountLimits::new(limits))),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+            .as_ref()
+            .map(|account_limits| account_limits.limits())
+            .unwrap_or_default()
+    }
+
+    /// Starts counting disputes, remembering the ones opened within `window`, if any. Deposits
+    /// already in the history that were ever disputed count as disputed deposits.
+    pub(crate) fn track_disputes(&mut self, window: Option<Window>) {
+        let disputed_deposits = self
+            .deposit_history
+            .iter()
+            .filter(|(_, deposit)| deposit.disputed)
+            .count();
+        self.dispute_activity = Some(Box::new(DisputeActivity::new(window, disputed_deposits)));
+    }
+
+    pub(crate) fn dispute_activity(&self) -> Option<&DisputeActivity> {
+        self.dispute_activity.as_deref()
+    }
+
+    /// Moves rolling limits and windows forward to the moment of the next operation. Nothing that
+    /// is still in a window at `horizon` is forgotten, so that a batch started then can be rolled
+    /// back.
+    pub(crate) fn advance(&mut self, now: Moment, horizon: Moment) {
+        if let Some(limits) = &mut self.limits {
+            limits.advance(now, horizon);
+        }
+        if let Some(activity) = &mut self.dispute_activity {
+            activity.advance(now, horizon);
+        }
+    }
+
     pub fn is_locked(&self) -> bool {
-        self.locked
+        self.lock_level() == Some(LockLevel::Locked)
+    }
+
+    /// Whether the account is frozen, but not locked.
+    pub fn is_frozen(&self) -> bool {
+        self.lock_level() == Some(LockLevel::Frozen)
+    }
+
+    /// How and when the account was locked or frozen, if it is.
+    pub fn lock(&self) -> Option<&Lock> {
+        self.lock.as_deref()
+    }
+
+    /// Why the account was locked or frozen, if it is.
+    pub fn lock_reason(&self) -> Option<LockReason> {
+        self.lock().map(|lock| lock.reason)
+    }
+
+    fn lock_level(&self) -> Option<LockLevel> {
+        self.lock().map(|lock| lock.level)
+    }
+
+    /// Locks or freezes the account. A lock replaces a freeze, but otherwise the first lock is
+    /// kept.
+    pub fn set_lock(&mut self, lock: Lock) {
+        if self.lock_level() < Some(lock.level) {
+            self.lock = Some(Box::new(lock));
+        }
+    }
+
+    /// Records when the account was locked, unless that is already known.
+    pub(crate) fn set_lock_sequence(&mut self, sequence: u64) {
+        if let Some(lock) = &mut self.lock {
+            lock.sequence.get_or_insert(sequence);
+        }
     }
 
     pub fn available_funds(&self) -> Amount {
@@ -178,8 +711,151 @@ impl Account {
         self.held_funds
     }
 
+    /// Funds of requested withdrawals that haven't been settled or cancelled yet.
+    pub fn reserved_funds(&self) -> Amount {
+        self.reserved_funds
+    }
+
+    /// Funds of deposits that haven't cleared yet, which can't be withdrawn.
+    pub fn pending_funds(&self) -> Amount {
+        self.pending_funds
+    }
+
     pub fn total_funds(&self) -> Amount {
-        self.available_funds + self.held_funds
+        self.available_funds + self.held_funds + self.reserved_funds + self.pending_funds
+    }
+
+    /// A previously processed deposit, regardless of its current state.
+    pub fn processed_deposit(&self, transaction_id: TransactionId) -> Option<&ProcessedDeposit> {
+        self.deposit_history.get(transaction_id)
+    }
+
+    /// A previously requested withdrawal, regardless of its current state.
+    pub fn requested_withdrawal(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Option<&RequestedWithdrawal> {
+        self.withdrawal_history.get(transaction_id)
+    }
+
+    /// Every deposit in the history of the account, in no particular order.
+    pub fn processed_deposits(
+        &self,
+    ) -> impl Iterator<Item = (TransactionId, &ProcessedDeposit)> + '_ {
+        self.deposit_history.iter()
+    }
+
+    /// Every withdrawal requested from the account, in no particular order.
+    pub fn requested_withdrawals(
+        &self,
+    ) -> impl Iterator<Item = (TransactionId, &RequestedWithdrawal)> + '_ {
+        self.withdrawal_history.iter()
+    }
+
+    /// The withdrawals kept for a rolling limit, with when they were made.
+    pub(crate) fn recent_withdrawals(&self) -> impl Iterator<Item = (Moment, Amount)> + '_ {
+        self.limits.iter().flat_map(|limits| limits.withdrawals())
+    }
+
+    /// When the disputes kept for risk rules that count recent ones were opened.
+    pub(crate) fn recent_disputes(&self) -> impl Iterator<Item = Moment> + '_ {
+        self.dispute_activity
+            .iter()
+            .flat_map(|activity| activity.disputes())
+    }
+
+    /// Builds an account back from its funds and lock, as saved in a snapshot. Its history is
+    /// restored separately, and nothing is checked against it here.
+    pub(crate) fn restored(
+        available: Amount,
+        held: Amount,
+        reserved: Amount,
+        pending: Amount,
+        lock: Option<Lock>,
+    ) -> Self {
+        Self {
+            lock: lock.map(Box::new),
+            available_funds: available,
+            held_funds: held,
+            reserved_funds: reserved,
+            pending_funds: pending,
+            ..Self::default()
+        }
+    }
+
+    /// Puts a deposit back into the history as it was, without moving any funds.
+    pub(crate) fn restore_deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        deposit: ProcessedDeposit,
+    ) -> Result<(), DepositError> {
+        self.deposit_history.insert(transaction_id, deposit)
+    }
+
+    /// Puts a requested withdrawal back into the history as it was, without moving any funds.
+    pub(crate) fn restore_withdrawal(
+        &mut self,
+        transaction_id: TransactionId,
+        withdrawal: RequestedWithdrawal,
+    ) -> Result<(), WithdrawError> {
+        if self.withdrawal_history.get(transaction_id).is_some() {
+            return Err(WithdrawError::DuplicateTransactionId(transaction_id));
+        }
+        self.withdrawal_history
+            .restore(transaction_id, Some(withdrawal));
+        Ok(())
+    }
+
+    /// Records a withdrawal made at `then` towards the rolling limit, if there is one.
+    pub(crate) fn restore_recent_withdrawal(&mut self, then: Moment, amount: Amount) {
+        if let Some(limits) = &mut self.limits {
+            limits.restore_withdrawal(then, amount);
+        }
+    }
+
+    /// Records a dispute opened at `then` for risk rules, if any counts recent disputes.
+    pub(crate) fn restore_recent_dispute(&mut self, then: Moment) {
+        if let Some(activity) = &mut self.dispute_activity {
+            activity.restore_dispute(then);
+        }
+    }
+
+    /// Every deposit that is currently disputed, with its amount, in no particular order.
+    pub fn open_disputes(&self) -> impl Iterator<Item = (TransactionId, Amount)> + '_ {
+        self.deposit_history
+            .iter()
+            .filter(|(_, deposit)| deposit.state == DepositState::Disputed)
+            .map(|(transaction_id, deposit)| (transaction_id, deposit.amount))
+    }
+
+    /// Number of deposits kept in the history of this account.
+    pub(crate) fn deposit_count(&self) -> usize {
+        self.deposit_history.len()
+    }
+
+    /// Number of deposits that are currently disputed.
+    pub(crate) fn dispute_count(&self) -> usize {
+        self.deposit_history.count(DepositState::Disputed)
+    }
+
+    /// Sum of every deposit that is currently disputed.
+    pub(crate) fn disputed_funds(&self) -> Amount {
+        self.deposit_history.sum(DepositState::Disputed)
+    }
+
+    /// Sum of every deposit that has been charged back.
+    pub(crate) fn reversed_funds(&self) -> Amount {
+        self.deposit_history.sum(DepositState::Reversed)
+    }
+
+    /// Sum of every deposit that hasn't cleared yet.
+    pub(crate) fn uncleared_funds(&self) -> Amount {
+        self.deposit_history.sum(DepositState::Pending)
+    }
+
+    /// Sum of every requested withdrawal that is still reserved.
+    pub(crate) fn requested_funds(&self) -> Amount {
+        self.withdrawal_history.sum(WithdrawalState::Reserved)
     }
 
     pub fn deposit(
@@ -187,55 +863,356 @@ impl Account {
         transaction_id: TransactionId,
         amount: Amount,
     ) -> Result<(), DepositError> {
-        if self.locked {
+        let deposit = ProcessedDeposit::new(DepositState::MaybeSettled, amount);
+        self.insert_deposit(
+            transaction_id,
+            deposit,
+            Transfer {
+                available: amount,
+                ..Transfer::default()
+            },
+        )
+    }
+
+    /// Deposits funds that stay pending, and can neither be withdrawn nor disputed, until the
+    /// deposit is cleared with `clear_deposit`.
+    pub fn deposit_pending(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), DepositError> {
+        let deposit = ProcessedDeposit::new(DepositState::Pending, amount);
+        self.insert_deposit(
+            transaction_id,
+            deposit,
+            Transfer {
+                pending: amount,
+                ..Transfer::default()
+            },
+        )
+    }
+
+    fn insert_deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        deposit: ProcessedDeposit,
+        transfer: Transfer,
+    ) -> Result<(), DepositError> {
+        if self.is_locked() {
             return Err(DepositError::AccountLocked);
         }
 
-        self.deposit_history.insert(transaction_id, amount)?;
-        self.available_funds += amount;
+        if let Some(max) = self.limits().max_balance {
+            if self
+                .total_funds()
+                .checked_add(deposit.amount)
+                .is_none_or(|total| total > max)
+            {
+                return Err(DepositError::ExceedsMaxBalance(max));
+            }
+        }
+
+        self.deposit_history.insert(transaction_id, deposit)?;
+        if !self.move_funds(transfer) {
+            self.deposit_history.restore(transaction_id, None);
+            return Err(DepositError::Overflow);
+        }
+
+        Ok(())
+    }
 
+    /// Makes the funds of a pending deposit available. Deposits to locked accounts still clear.
+    pub fn clear_deposit(&mut self, transaction_id: TransactionId) -> Result<(), ClearError> {
+        let previous = self.deposit_history.get(transaction_id).copied();
+        let amount = *self.deposit_history.clear(transaction_id)?;
+        if !self.move_funds(Transfer {
+            available: amount,
+            pending: -amount,
+            ..Transfer::default()
+        }) {
+            self.deposit_history.restore(transaction_id, previous);
+            return Err(ClearError::Overflow);
+        }
         Ok(())
     }
 
     pub fn withdraw(&mut self, amount: Amount) -> Result<(), WithdrawError> {
-        if self.locked {
+        if self.is_locked() {
             return Err(WithdrawError::AccountLocked);
         }
+        if self.is_frozen() {
+            return Err(WithdrawError::AccountFrozen);
+        }
+
+        self.check_withdrawal_limits(amount)?;
 
         if self.available_funds < amount {
             return Err(WithdrawError::InsufficientFunds);
         }
 
-        self.available_funds -= amount;
+        if !self.move_funds(Transfer {
+            available: -amount,
+            ..Transfer::default()
+        }) {
+            return Err(WithdrawError::Overflow);
+        }
 
+        self.record_withdrawal(amount);
+        Ok(())
+    }
+
+    fn check_withdrawal_limits(&self, amount: Amount) -> Result<(), WithdrawError> {
+        let account_limits = match &self.limits {
+            Some(account_limits) => account_limits,
+            None => return Ok(()),
+        };
+        let limits = account_limits.limits();
+        if let Some(max) = limits.max_withdrawal {
+            if amount > max {
+                return Err(WithdrawError::ExceedsMaxWithdrawal(max));
+            }
+        }
+        if let Some((max, window)) = limits.max_withdrawn {
+            if account_limits
+                .withdrawn()
+                .checked_add(amount)
+                .is_none_or(|withdrawn| withdrawn > max)
+            {
+                return Err(WithdrawError::ExceedsMaxWithdrawn(max, window));
+            }
+        }
+        Ok(())
+    }
+
+    fn record_withdrawal(&mut self, amount: Amount) {
+        if let Some(limits) = &mut self.limits {
+            limits.record_withdrawal(amount);
+        }
+    }
+
+    /// Reserves funds for a withdrawal that will be settled or cancelled later. Reserved funds
+    /// still count towards the total, but can't be withdrawn again.
+    pub fn request_withdrawal(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), WithdrawError> {
+        if self.is_locked() {
+            return Err(WithdrawError::AccountLocked);
+        }
+        if self.is_frozen() {
+            return Err(WithdrawError::AccountFrozen);
+        }
+
+        self.check_withdrawal_limits(amount)?;
+
+        if self.available_funds < amount {
+            return Err(WithdrawError::InsufficientFunds);
+        }
+
+        self.withdrawal_history.insert(transaction_id, amount)?;
+        if !self.move_funds(Transfer {
+            available: -amount,
+            reserved: amount,
+            ..Transfer::default()
+        }) {
+            self.withdrawal_history.restore(transaction_id, None);
+            return Err(WithdrawError::Overflow);
+        }
+
+        self.record_withdrawal(amount);
+        Ok(())
+    }
+
+    /// Pays out the reserved funds of a requested withdrawal.
+    pub fn settle_withdrawal(
+        &mut self,
+        transaction_id: TransactionId,
+    ) -> Result<(), WithdrawalStateError> {
+        let previous = self.withdrawal_history.get(transaction_id).copied();
+        let amount = *self.withdrawal_history.settle(transaction_id)?;
+        if !self.move_funds(Transfer {
+            reserved: -amount,
+            ..Transfer::default()
+        }) {
+            self.withdrawal_history.restore(transaction_id, previous);
+            return Err(WithdrawalStateError::Overflow);
+        }
+        Ok(())
+    }
+
+    /// Returns the reserved funds of a requested withdrawal to the available funds.
+    pub fn cancel_withdrawal(
+        &mut self,
+        transaction_id: TransactionId,
+    ) -> Result<(), WithdrawalStateError> {
+        let previous = self.withdrawal_history.get(transaction_id).copied();
+        let amount = *self.withdrawal_history.cancel(transaction_id)?;
+        if !self.move_funds(Transfer {
+            available: amount,
+            reserved: -amount,
+            ..Transfer::default()
+        }) {
+            self.withdrawal_history.restore(transaction_id, previous);
+            return Err(WithdrawalStateError::Overflow);
+        }
         Ok(())
     }
 
     pub fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), DisputeError> {
-        let amount = self.deposit_history.dispute(transaction_id)?;
-        self.available_funds -= *amount;
-        self.held_funds += *amount;
+        let previous = self.deposit_history.get(transaction_id).copied();
+        let amount = *self.deposit_history.dispute(transaction_id)?;
+        // The deposit being disputed now counts as open.
+        if let Some(max) = self.limits().max_open_disputes {
+            if self.dispute_count() > max {
+                self.deposit_history.restore(transaction_id, previous);
+                return Err(DisputeError::TooManyOpenDisputes(max));
+            }
+        }
+        if !self.move_funds(Transfer {
+            available: -amount,
+            held: amount,
+            ..Transfer::default()
+        }) {
+            self.deposit_history.restore(transaction_id, previous);
+            return Err(DisputeError::Overflow);
+        }
+        if let Some(activity) = &mut self.dispute_activity {
+            let first = previous.is_some_and(|deposit| !deposit.disputed);
+            activity.record_dispute(first);
+        }
         Ok(())
     }
 
     pub fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), ResolveError> {
-        let amount = self.deposit_history.resolve(transaction_id)?;
-        self.held_funds -= *amount;
-        self.available_funds += *amount;
+        let previous = self.deposit_history.get(transaction_id).copied();
+        let amount = *self.deposit_history.resolve(transaction_id)?;
+        if !self.move_funds(Transfer {
+            available: amount,
+            held: -amount,
+            ..Transfer::default()
+        }) {
+            self.deposit_history.restore(transaction_id, previous);
+            return Err(ResolveError::Overflow);
+        }
         Ok(())
     }
 
     pub fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), ChargebackError> {
-        let amount = self.deposit_history.chargeback(transaction_id)?;
-        self.held_funds -= *amount;
-        self.locked = true;
+        let previous = self.deposit_history.get(transaction_id).copied();
+        let amount = *self.deposit_history.chargeback(transaction_id)?;
+        if !self.move_funds(Transfer {
+            held: -amount,
+            ..Transfer::default()
+        }) {
+            self.deposit_history.restore(transaction_id, previous);
+            return Err(ChargebackError::Overflow);
+        }
+        self.set_lock(Lock::new(LockReason::Chargeback).with_transaction_id(transaction_id));
         Ok(())
     }
+
+    /// Adds to each kind of funds, unless any of them or their total can't be represented
+    /// exactly, in which case nothing changes and `false` is returned. This keeps `total_funds`
+    /// from ever overflowing.
+    fn move_funds(&mut self, transfer: Transfer) -> bool {
+        // Adding zero to a zero balance would replace its scale, changing how it is written out.
+        let add = |funds: Amount, change: Amount| {
+            if change == Amount::default() {
+                Some(funds)
+            } else {
+                funds.checked_add(change)
+            }
+        };
+        let available = add(self.available_funds, transfer.available);
+        let held = add(self.held_funds, transfer.held);
+        let reserved = add(self.reserved_funds, transfer.reserved);
+        let pending = add(self.pending_funds, transfer.pending);
+        match (available, held, reserved, pending) {
+            (Some(available), Some(held), Some(reserved), Some(pending))
+                if available
+                    .checked_add(held)
+                    .and_then(|total| total.checked_add(reserved))
+                    .and_then(|total| total.checked_add(pending))
+                    .is_some() =>
+            {
+                self.available_funds = available;
+                self.held_funds = held;
+                self.reserved_funds = reserved;
+                self.pending_funds = pending;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Captures everything that an operation on `transaction_id` could change.
+    pub(crate) fn checkpoint(&self, transaction_id: TransactionId) -> Checkpoint {
+        Checkpoint {
+            lock: self.lock().copied(),
+            available_funds: self.available_funds,
+            held_funds: self.held_funds,
+            reserved_funds: self.reserved_funds,
+            pending_funds: self.pending_funds,
+            transaction_id,
+            deposit: self.deposit_history.get(transaction_id).copied(),
+            withdrawal: self.withdrawal_history.get(transaction_id).copied(),
+            withdrawals_recorded: self.limits.as_ref().map_or(0, |limits| limits.recorded()),
+            disputes_recorded: self
+                .dispute_activity
+                .as_ref()
+                .map_or(0, |activity| activity.recorded()),
+            disputed_deposits: self
+                .dispute_activity
+                .as_ref()
+                .map_or(0, |activity| activity.disputed_deposits()),
+        }
+    }
+
+    pub(crate) fn restore(&mut self, checkpoint: Checkpoint) {
+        self.lock = checkpoint.lock.map(Box::new);
+        self.available_funds = checkpoint.available_funds;
+        self.held_funds = checkpoint.held_funds;
+        self.reserved_funds = checkpoint.reserved_funds;
+        self.pending_funds = checkpoint.pending_funds;
+        self.deposit_history
+            .restore(checkpoint.transaction_id, checkpoint.deposit);
+        self.withdrawal_history
+            .restore(checkpoint.transaction_id, checkpoint.withdrawal);
+        if let Some(limits) = &mut self.limits {
+            limits.undo(checkpoint.withdrawals_recorded);
+        }
+        if let Some(activity) = &mut self.dispute_activity {
+            activity.undo(checkpoint.disputes_recorded, checkpoint.disputed_deposits);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use {super::*, rust_decimal_macros::dec};
+    use {super::*, rust_decimal::Decimal, rust_decimal_macros::dec};
+
+    #[test]
+    fn can_restore_checkpoint() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let checkpoint = account.checkpoint(TransactionId::from(2));
+        let b = account.deposit(TransactionId::from(2), Amount::from(dec!(10)));
+        let c = account.dispute(TransactionId::from(2));
+        let d = account.chargeback(TransactionId::from(2));
+        account.restore(checkpoint);
+
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert!(c.is_ok());
+        assert!(d.is_ok());
+        assert!(!account.is_locked());
+        assert!(account.processed_deposit(TransactionId::from(2)).is_none());
+        assert_eq!(account.available_funds(), Amount::from(dec!(150.99)));
+        assert_eq!(account.held_funds(), Amount::from(dec!(0)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(150.99)));
+    }
 
     #[test]
     fn can_deposit_funds() {
@@ -440,4 +1417,256 @@ mod tests {
         assert_eq!(account.held_funds(), Amount::from(dec!(0)));
         assert_eq!(account.total_funds(), Amount::from(dec!(123.45)));
     }
+
+    #[test]
+    fn frozen_account_keeps_its_funds_until_locked() {
+        let mut account = Account::new();
+        account
+            .deposit(TransactionId::from(1), Amount::from(dec!(100)))
+            .unwrap();
+        account.set_lock(Lock::new(LockReason::NegativeBalance).with_level(LockLevel::Frozen));
+
+        let a = account.withdraw(Amount::from(dec!(10)));
+        let b = account.request_withdrawal(TransactionId::from(2), Amount::from(dec!(10)));
+        let c = account.deposit(TransactionId::from(3), Amount::from(dec!(10)));
+        let d = account.dispute(TransactionId::from(1));
+        let e = account.chargeback(TransactionId::from(1));
+
+        assert!(matches!(a, Err(WithdrawError::AccountFrozen)));
+        assert!(matches!(b, Err(WithdrawError::AccountFrozen)));
+        assert!(c.is_ok());
+        assert!(d.is_ok());
+        assert!(e.is_ok());
+        assert!(account.is_locked());
+        assert!(!account.is_frozen());
+        assert_eq!(account.lock_reason(), Some(LockReason::Chargeback));
+        assert_eq!(account.total_funds(), Amount::from(dec!(10)));
+    }
+
+    #[test]
+    fn rejects_overflowing_funds() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(Decimal::MAX));
+        let b = account.deposit(TransactionId::from(2), Amount::from(dec!(0.0001)));
+        let c = account.withdraw(Amount::from(dec!(-1)));
+
+        assert!(a.is_ok());
+        assert!(matches!(b, Err(DepositError::Overflow)));
+        assert!(matches!(c, Err(WithdrawError::Overflow)));
+        assert!(account.processed_deposit(TransactionId::from(2)).is_none());
+        assert_eq!(account.available_funds(), Amount::from(Decimal::MAX));
+        assert_eq!(account.total_funds(), Amount::from(Decimal::MAX));
+    }
+
+    #[test]
+    fn lists_open_disputes() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let b = account.deposit(TransactionId::from(2), Amount::from(dec!(10)));
+        let c = account.deposit(TransactionId::from(3), Amount::from(dec!(20)));
+        let d = account.dispute(TransactionId::from(1));
+        let e = account.dispute(TransactionId::from(2));
+        let f = account.dispute(TransactionId::from(3));
+        let g = account.resolve(TransactionId::from(2));
+        let h = account.chargeback(TransactionId::from(3));
+
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert!(c.is_ok());
+        assert!(d.is_ok());
+        assert!(e.is_ok());
+        assert!(f.is_ok());
+        assert!(g.is_ok());
+        assert!(h.is_ok());
+        assert_eq!(
+            account.open_disputes().collect::<Vec<_>>(),
+            vec![(TransactionId::from(1), Amount::from(dec!(150.99)))]
+        );
+        assert_eq!(
+            account
+                .processed_deposit(TransactionId::from(2))
+                .map(ProcessedDeposit::state),
+            Some(DepositState::MaybeSettled)
+        );
+        assert_eq!(
+            account
+                .processed_deposit(TransactionId::from(3))
+                .map(ProcessedDeposit::state),
+            Some(DepositState::Reversed)
+        );
+        assert!(account.processed_deposit(TransactionId::from(4)).is_none());
+    }
+
+    #[test]
+    fn can_settle_requested_withdrawal() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let b = account.request_withdrawal(TransactionId::from(2), Amount::from(dec!(50)));
+        let reserved = account.reserved_funds();
+        let c = account.settle_withdrawal(TransactionId::from(2));
+        let d = account.cancel_withdrawal(TransactionId::from(2));
+
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert_eq!(reserved, Amount::from(dec!(50)));
+        assert!(c.is_ok());
+        assert!(matches!(
+            d,
+            Err(WithdrawalStateError::WithdrawalAlreadySettled)
+        ));
+        assert_eq!(account.available_funds(), Amount::from(dec!(100.99)));
+        assert_eq!(account.reserved_funds(), Amount::from(dec!(0)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(100.99)));
+    }
+
+    #[test]
+    fn can_cancel_requested_withdrawal() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let b = account.request_withdrawal(TransactionId::from(2), Amount::from(dec!(50)));
+        let c = account.withdraw(Amount::from(dec!(110)));
+        let d = account.cancel_withdrawal(TransactionId::from(2));
+        let e = account.settle_withdrawal(TransactionId::from(2));
+
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert!(matches!(c, Err(WithdrawError::InsufficientFunds)));
+        assert!(d.is_ok());
+        assert!(matches!(
+            e,
+            Err(WithdrawalStateError::WithdrawalAlreadyCancelled)
+        ));
+        assert_eq!(
+            account
+                .requested_withdrawal(TransactionId::from(2))
+                .map(RequestedWithdrawal::state),
+            Some(WithdrawalState::Cancelled)
+        );
+        assert_eq!(account.available_funds(), Amount::from(dec!(150.99)));
+        assert_eq!(account.reserved_funds(), Amount::from(dec!(0)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(150.99)));
+    }
+
+    #[test]
+    fn can_settle_withdrawal_after_account_is_locked() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let b = account.deposit(TransactionId::from(2), Amount::from(dec!(10)));
+        let c = account.request_withdrawal(TransactionId::from(3), Amount::from(dec!(100)));
+        let d = account.dispute(TransactionId::from(2));
+        let e = account.chargeback(TransactionId::from(2));
+        let f = account.request_withdrawal(TransactionId::from(4), Amount::from(dec!(1)));
+        let g = account.settle_withdrawal(TransactionId::from(3));
+        let h = account.settle_withdrawal(TransactionId::from(4));
+
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert!(c.is_ok());
+        assert!(d.is_ok());
+        assert!(e.is_ok());
+        assert!(matches!(f, Err(WithdrawError::AccountLocked)));
+        assert!(g.is_ok());
+        assert!(matches!(
+            h,
+            Err(WithdrawalStateError::WithdrawalDoesNotExist)
+        ));
+        assert_eq!(account.available_funds(), Amount::from(dec!(50.99)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(50.99)));
+    }
+
+    #[test]
+    fn rejects_duplicate_withdrawal_request() {
+        let mut account = Account::new();
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let b = account.request_withdrawal(TransactionId::from(2), Amount::from(dec!(50)));
+        let checkpoint = account.checkpoint(TransactionId::from(2));
+        let c = account.request_withdrawal(TransactionId::from(2), Amount::from(dec!(10)));
+        let d = account.settle_withdrawal(TransactionId::from(2));
+        account.restore(checkpoint);
+
+        assert!(a.is_ok());
+        assert!(b.is_ok());
+        assert!(matches!(c, Err(WithdrawError::DuplicateTransactionId(_))));
+        assert!(d.is_ok());
+        assert_eq!(account.available_funds(), Amount::from(dec!(100.99)));
+        assert_eq!(account.reserved_funds(), Amount::from(dec!(50)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(150.99)));
+    }
+
+    #[test]
+    fn pending_deposit_is_unavailable_until_cleared() {
+        let mut account = Account::new();
+
+        let a = account.deposit_pending(TransactionId::from(1), Amount::from(dec!(150.99)));
+        let b = account.withdraw(Amount::from(dec!(10)));
+        let c = account.dispute(TransactionId::from(1));
+        let pending = account.pending_funds();
+        let d = account.clear_deposit(TransactionId::from(1));
+        let e = account.clear_deposit(TransactionId::from(1));
+        let f = account.withdraw(Amount::from(dec!(10)));
+
+        assert!(a.is_ok());
+        assert!(matches!(b, Err(WithdrawError::InsufficientFunds)));
+        assert!(matches!(c, Err(DisputeError::DepositPending)));
+        assert_eq!(pending, Amount::from(dec!(150.99)));
+        assert!(d.is_ok());
+        assert!(matches!(e, Err(ClearError::DepositAlreadyCleared)));
+        assert!(f.is_ok());
+        assert_eq!(account.available_funds(), Amount::from(dec!(140.99)));
+        assert_eq!(account.pending_funds(), Amount::from(dec!(0)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(140.99)));
+    }
+
+    #[test]
+    fn rejects_operations_past_limits() {
+        let mut account = Account::new();
+        account.set_limits(Limits {
+            max_withdrawal: Some(Amount::from(dec!(50))),
+            max_withdrawn: Some((Amount::from(dec!(80)), Window::Events(3))),
+            max_balance: Some(Amount::from(dec!(200))),
+            max_open_disputes: Some(1),
+        });
+        let at = |events| Moment { events, time: 0 };
+
+        let a = account.deposit(TransactionId::from(1), Amount::from(dec!(150)));
+        let b = account.deposit(TransactionId::from(2), Amount::from(dec!(60)));
+        let c = account.withdraw(Amount::from(dec!(60)));
+        account.advance(at(1), at(1));
+        let d = account.withdraw(Amount::from(dec!(50)));
+        account.advance(at(2), at(2));
+        let e = account.request_withdrawal(TransactionId::from(3), Amount::from(dec!(40)));
+        account.advance(at(4), at(4));
+        let f = account.request_withdrawal(TransactionId::from(3), Amount::from(dec!(40)));
+        let g = account.deposit(TransactionId::from(4), Amount::from(dec!(10)));
+        let h = account.dispute(TransactionId::from(1));
+        let i = account.dispute(TransactionId::from(4));
+        let j = account.dispute(TransactionId::from(1));
+        let k = account.dispute(TransactionId::from(5));
+
+        assert!(a.is_ok());
+        assert!(matches!(b, Err(DepositError::ExceedsMaxBalance(_))));
+        assert!(matches!(c, Err(WithdrawError::ExceedsMaxWithdrawal(_))));
+        assert!(d.is_ok());
+        assert!(matches!(e, Err(WithdrawError::ExceedsMaxWithdrawn(..))));
+        assert!(f.is_ok());
+        assert!(g.is_ok());
+        assert!(h.is_ok());
+        assert!(matches!(i, Err(DisputeError::TooManyOpenDisputes(1))));
+        assert!(matches!(j, Err(DisputeError::DepositAlreadyDisputed)));
+        assert!(matches!(k, Err(DisputeError::DepositDoesNotExist)));
+        assert_eq!(
+            account
+                .processed_deposit(TransactionId::from(4))
+                .map(ProcessedDeposit::state),
+            Some(DepositState::MaybeSettled)
+        );
+        assert_eq!(account.available_funds(), Amount::from(dec!(-80)));
+        assert_eq!(account.total_funds(), Amount::from(dec!(110)));
+    }
 }
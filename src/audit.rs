@@ -0,0 +1,337 @@
+use {
+    crate::{account::Account, event::EventKind, Amount, ClientId},
+    log::warn,
+    std::collections::HashMap,
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+pub enum InvariantError {
+    #[error(
+        "Client {client}: total {total} is not deposits less withdrawals and chargebacks {expected}"
+    )]
+    TotalMismatch {
+        client: ClientId,
+        total: Amount,
+        expected: Amount,
+    },
+    #[error("Client {client}: held {held} is not the sum of disputed deposits {disputed}")]
+    HeldMismatch {
+        client: ClientId,
+        held: Amount,
+        disputed: Amount,
+    },
+    #[error("Client {client}: held {held} includes reversed deposits worth {reversed}")]
+    ReversedDepositHeld {
+        client: ClientId,
+        held: Amount,
+        reversed: Amount,
+    },
+    #[error(
+        "Client {client}: reserved {reserved} is not the sum of requested withdrawals {requested}"
+    )]
+    ReservedMismatch {
+        client: ClientId,
+        reserved: Amount,
+        requested: Amount,
+    },
+    #[error("Client {client}: pending {pending} is not the sum of uncleared deposits {uncleared}")]
+    PendingMismatch {
+        client: ClientId,
+        pending: Amount,
+        uncleared: Amount,
+    },
+    #[error("System total {actual} does not match deposits less withdrawals {expected}")]
+    SystemTotalMismatch { actual: Amount, expected: Amount },
+}
+
+/// Independently tracks the money entering and leaving the system so that the state of every
+/// account can be cross-checked against it.
+#[derive(Debug, Default, Clone)]
+pub struct Auditor {
+    /// Funds the accounts already held when the auditor started, such as those restored from a
+    /// snapshot.
+    opening_total: Amount,
+    deposits: Amount,
+    withdrawals: Amount,
+    chargebacks: Amount,
+    /// Money that entered each account less the money that left it, including its opening funds.
+    clients: HashMap<ClientId, Amount>,
+    /// Sum of the changes to account totals observed for every applied event.
+    observed_total: Amount,
+    /// Set once the system-wide totals no longer fit in an `Amount`, after which only individual
+    /// accounts can be checked.
+    overflowed: bool,
+}
+
+impl Auditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a successfully applied event along with the total funds of its account before and
+    /// after it was applied.
+    pub fn record(
+        &mut self,
+        client: ClientId,
+        kind: EventKind,
+        amount: Amount,
+        total_before: Amount,
+        total_after: Amount,
+    ) {
+        if self.overflowed {
+            return;
+        }
+        let net = self.clients.entry(client).or_default();
+        let recorded = match kind {
+            EventKind::Deposit => add(&mut self.deposits, amount) && add(net, amount),
+            EventKind::Withdrawal | EventKind::WithdrawalSettle => {
+                add(&mut self.withdrawals, amount) && add(net, -amount)
+            }
+            EventKind::Chargeback => add(&mut self.chargebacks, amount) && add(net, -amount),
+            EventKind::Dispute
+            | EventKind::Resolve
+            | EventKind::WithdrawalRequest
+            | EventKind::WithdrawalCancel
+            | EventKind::Clear => true,
+        } && total_after
+            .checked_sub(total_before)
+            .is_some_and(|change| add(&mut self.observed_total, change));
+        if !recorded {
+            warn!("System totals exceed the largest representable amount, no longer checking them");
+            self.overflowed = true;
+        }
+    }
+
+    /// Records funds that an account already held when the auditor started.
+    pub fn record_opening(&mut self, client: ClientId, total: Amount) {
+        if self.overflowed {
+            return;
+        }
+        let net = self.clients.entry(client).or_default();
+        if !(add(&mut self.opening_total, total)
+            && add(&mut self.observed_total, total)
+            && add(net, total))
+        {
+            warn!("System totals exceed the largest representable amount, no longer checking them");
+            self.overflowed = true;
+        }
+    }
+
+    fn expected_total(&self) -> Option<Amount> {
+        if self.overflowed {
+            return None;
+        }
+        self.opening_total
+            .checked_add(self.deposits)?
+            .checked_sub(self.withdrawals)?
+            .checked_sub(self.chargebacks)
+    }
+
+    /// Checks the invariants that must hold for every individual account, including that its total
+    /// is the money recorded entering it less the money recorded leaving it.
+    pub fn check_account(&self, client: ClientId, account: &Account) -> Result<(), InvariantError> {
+        Self::check_balances(client, account)?;
+        if self.overflowed {
+            return Ok(());
+        }
+        let total = account.total_funds();
+        let expected = self.clients.get(&client).copied().unwrap_or_default();
+        if total != expected {
+            return Err(InvariantError::TotalMismatch {
+                client,
+                total,
+                expected,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the balances of an account match its history, without needing to know how
+    /// money entered or left it.
+    pub fn check_balances(client: ClientId, account: &Account) -> Result<(), InvariantError> {
+        let held = account.held_funds();
+        let reserved = account.reserved_funds();
+        let pending = account.pending_funds();
+
+        let requested = account.requested_funds();
+        if reserved != requested {
+            return Err(InvariantError::ReservedMismatch {
+                client,
+                reserved,
+                requested,
+            });
+        }
+
+        let disputed = account.disputed_funds();
+        if held != disputed {
+            let reversed = account.reversed_funds();
+            if reversed != Amount::default() && disputed.checked_add(reversed) == Some(held) {
+                return Err(InvariantError::ReversedDepositHeld {
+                    client,
+                    held,
+                    reversed,
+                });
+            }
+            return Err(InvariantError::HeldMismatch {
+                client,
+                held,
+                disputed,
+            });
+        }
+
+        let uncleared = account.uncleared_funds();
+        if pending != uncleared {
+            return Err(InvariantError::PendingMismatch {
+                client,
+                pending,
+                uncleared,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the changes to account totals observed so far add up to the money that entered
+    /// and left the system.
+    pub fn check_running_total(&self) -> Result<(), InvariantError> {
+        let expected = match self.expected_total() {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        if self.observed_total != expected {
+            return Err(InvariantError::SystemTotalMismatch {
+                actual: self.observed_total,
+                expected,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks every account and the system-wide total from scratch.
+    pub fn check_all<'a>(
+        &self,
+        accounts: impl IntoIterator<Item = (ClientId, &'a Account)>,
+    ) -> Result<(), InvariantError> {
+        let mut actual = Some(Amount::default());
+        for (client, account) in accounts {
+            self.check_account(client, account)?;
+            actual = actual.and_then(|actual| actual.checked_add(account.total_funds()));
+        }
+
+        match (actual, self.expected_total()) {
+            (Some(actual), Some(expected)) if actual != expected => {
+                Err(InvariantError::SystemTotalMismatch { actual, expected })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Adds to a running total, returning `false` if the sum can't be represented.
+fn add(total: &mut Amount, amount: Amount) -> bool {
+    match total.checked_add(amount) {
+        Some(sum) => {
+            *total = sum;
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::TransactionId, rust_decimal_macros::dec};
+
+    #[test]
+    fn consistent_accounts_pass() {
+        let mut auditor = Auditor::new();
+        let mut account = Account::new();
+
+        account
+            .deposit(TransactionId::from(1), Amount::from(dec!(10)))
+            .unwrap();
+        auditor.record(
+            ClientId::from(1),
+            EventKind::Deposit,
+            Amount::from(dec!(10)),
+            Amount::from(dec!(0)),
+            account.total_funds(),
+        );
+        account.dispute(TransactionId::from(1)).unwrap();
+        auditor.record(
+            ClientId::from(1),
+            EventKind::Dispute,
+            Amount::from(dec!(10)),
+            Amount::from(dec!(10)),
+            account.total_funds(),
+        );
+
+        assert!(auditor.check_account(ClientId::from(1), &account).is_ok());
+        assert!(auditor.check_running_total().is_ok());
+        assert!(auditor
+            .check_all(vec![(ClientId::from(1), &account)])
+            .is_ok());
+    }
+
+    #[test]
+    fn unrecorded_funds_fail() {
+        let auditor = Auditor::new();
+        let mut account = Account::new();
+
+        account
+            .deposit(TransactionId::from(1), Amount::from(dec!(10)))
+            .unwrap();
+
+        assert!(Auditor::check_balances(ClientId::from(1), &account).is_ok());
+        assert!(matches!(
+            auditor.check_account(ClientId::from(1), &account),
+            Err(InvariantError::TotalMismatch { .. })
+        ));
+        assert!(matches!(
+            auditor.check_all(vec![(ClientId::from(1), &account)]),
+            Err(InvariantError::TotalMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn funds_recorded_for_another_client_fail() {
+        let mut auditor = Auditor::new();
+        let mut account = Account::new();
+
+        account
+            .deposit(TransactionId::from(1), Amount::from(dec!(10)))
+            .unwrap();
+        auditor.record(
+            ClientId::from(2),
+            EventKind::Deposit,
+            Amount::from(dec!(10)),
+            Amount::from(dec!(0)),
+            account.total_funds(),
+        );
+
+        assert!(auditor.check_running_total().is_ok());
+        assert!(matches!(
+            auditor.check_account(ClientId::from(1), &account),
+            Err(InvariantError::TotalMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn mismatched_running_total_fails() {
+        let mut auditor = Auditor::new();
+
+        auditor.record(
+            ClientId::from(1),
+            EventKind::Withdrawal,
+            Amount::from(dec!(5)),
+            Amount::from(dec!(10)),
+            Amount::from(dec!(10)),
+        );
+
+        assert!(matches!(
+            auditor.check_running_total(),
+            Err(InvariantError::SystemTotalMismatch { .. })
+        ));
+    }
+}
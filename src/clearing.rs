@@ -0,0 +1,162 @@
+use {
+    crate::{ClientId, Moment, TransactionId},
+    std::{collections::VecDeque, fmt, str::FromStr},
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+#[error("Invalid clearing policy: \"{0}\"")]
+pub struct InvalidClearingPolicy(String);
+
+/// When deposited funds become available.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ClearingPolicy {
+    /// Deposits are available as soon as they are applied.
+    #[default]
+    Immediate,
+    /// Deposits are pending until this many more events have been handled. Written as
+    /// `events:N`.
+    AfterEvents(u64),
+    /// Deposits are pending until the input's timestamps reach this many seconds past the time of
+    /// the deposit. Written as `seconds:N`.
+    AfterSeconds(u64),
+    /// Deposits are pending until a `clear` event for their transaction.
+    Explicit,
+}
+
+impl ClearingPolicy {
+    /// Whether deposits clear according to the input's timestamps.
+    pub fn uses_timestamps(self) -> bool {
+        matches!(self, Self::AfterSeconds(_))
+    }
+}
+
+impl FromStr for ClearingPolicy {
+    type Err = InvalidClearingPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidClearingPolicy(s.to_owned());
+        match s.split_once(':') {
+            None if s == "immediate" => Ok(Self::Immediate),
+            None if s == "explicit" => Ok(Self::Explicit),
+            Some(("events", n)) => n.parse().map(Self::AfterEvents).map_err(|_| invalid()),
+            Some(("seconds", n)) => n.parse().map(Self::AfterSeconds).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ClearingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Immediate => write!(f, "immediate"),
+            Self::AfterEvents(events) => write!(f, "events:{}", events),
+            Self::AfterSeconds(seconds) => write!(f, "seconds:{}", seconds),
+            Self::Explicit => write!(f, "explicit"),
+        }
+    }
+}
+
+/// Pending deposits waiting to clear on their own, in the order they come due.
+///
+/// Both the number of events handled and the latest time seen only ever go up, so deposits come
+/// due in the order they were made and a queue is enough to find the ones that are due.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ClearingQueue {
+    policy: ClearingPolicy,
+    deposits: VecDeque<(u64, ClientId, TransactionId)>,
+}
+
+impl ClearingQueue {
+    pub(crate) fn new(policy: ClearingPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn policy(&self) -> ClearingPolicy {
+        self.policy
+    }
+
+    /// Waits for a pending deposit made at `now` to come due, unless it can only be cleared
+    /// explicitly.
+    pub(crate) fn push(&mut self, client: ClientId, transaction_id: TransactionId, now: Moment) {
+        let due = match self.policy {
+            ClearingPolicy::AfterEvents(events) => now.events.saturating_add(events),
+            ClearingPolicy::AfterSeconds(seconds) => now.time.saturating_add(seconds),
+            ClearingPolicy::Immediate | ClearingPolicy::Explicit => return,
+        };
+        self.deposits.push_back((due, client, transaction_id));
+    }
+
+    /// Removes the next deposit that is due by `now`, if any.
+    pub(crate) fn pop_due(&mut self, now: Moment) -> Option<(ClientId, TransactionId)> {
+        let now = match self.policy {
+            ClearingPolicy::AfterEvents(_) => now.events,
+            ClearingPolicy::AfterSeconds(_) => now.time,
+            ClearingPolicy::Immediate | ClearingPolicy::Explicit => return None,
+        };
+        match self.deposits.front() {
+            Some((due, _, _)) if *due <= now => self
+                .deposits
+                .pop_front()
+                .map(|(_, client, transaction_id)| (client, transaction_id)),
+            _ => None,
+        }
+    }
+
+    /// Every deposit waiting to come due, with when it does, in the order it will.
+    pub(crate) fn deposits(&self) -> impl Iterator<Item = (u64, ClientId, TransactionId)> + '_ {
+        self.deposits.iter().copied()
+    }
+
+    /// Waits for a deposit to come due at `due`, as saved with `deposits`.
+    pub(crate) fn restore(&mut self, due: u64, client: ClientId, transaction_id: TransactionId) {
+        self.deposits.push_back((due, client, transaction_id));
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.deposits.len()
+    }
+
+    /// Forgets the deposits pushed after the first `len`, as long as none were popped since.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.deposits.truncate(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_clearing_policies() {
+        for policy in [
+            ClearingPolicy::Immediate,
+            ClearingPolicy::AfterEvents(3),
+            ClearingPolicy::AfterSeconds(86400),
+            ClearingPolicy::Explicit,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<ClearingPolicy>().unwrap(),
+                policy
+            );
+        }
+        assert!("events".parse::<ClearingPolicy>().is_err());
+        assert!("events:-1".parse::<ClearingPolicy>().is_err());
+        assert!("daily".parse::<ClearingPolicy>().is_err());
+    }
+
+    #[test]
+    fn deposits_come_due_in_order() {
+        let at = |events, time| Moment { events, time };
+        let mut queue = ClearingQueue::new(ClearingPolicy::AfterSeconds(10));
+        queue.push(1.into(), 1.into(), at(0, 100));
+        queue.push(2.into(), 2.into(), at(1, 100));
+        assert_eq!(queue.pop_due(at(2, 109)), None);
+        assert_eq!(queue.pop_due(at(2, 110)), Some((1.into(), 1.into())));
+        assert_eq!(queue.pop_due(at(2, 110)), Some((2.into(), 2.into())));
+        assert_eq!(queue.pop_due(at(2, 110)), None);
+    }
+}
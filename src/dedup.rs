@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+/// Name of the optional input column that uniquely identifies an event.
+pub const EVENT_ID: &str = "event_id";
+
+/// Identity of an input event, used to recognise events that have already been processed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventKey {
+    /// An explicit ID from the `event_id` column.
+    Id(String),
+    /// The position of the event in a named source, along with a hash of its fields from
+    /// `content_hash`, so that a row rewritten since it was processed is read as a new event.
+    Row {
+        source: String,
+        row: u64,
+        content: u64,
+    },
+}
+
+/// Hashes the fields of a row with 64-bit FNV-1a, which unlike the standard library's hasher is
+/// guaranteed to give the same hash in every run and build.
+pub fn content_hash<'a>(fields: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    // Bytes of UTF-8 text are never 0xff, so separating fields with it keeps `a,bc` and `ab,c`
+    // apart.
+    fields.into_iter().fold(OFFSET_BASIS, |hash, field| {
+        field.iter().chain(&[0xff]).fold(hash, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        })
+    })
+}
+
+/// Remembers which events have been processed, across any number of sources and runs.
+#[derive(Debug, Default, Clone)]
+pub struct Deduplicator {
+    seen: HashSet<EventKey>,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks an event as processed. Returns `false` if it already was.
+    pub fn insert(&mut self, key: EventKey) -> bool {
+        self.seen.insert(key)
+    }
+
+    pub fn contains(&self, key: &EventKey) -> bool {
+        self.seen.contains(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Every event marked as processed, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &EventKey> {
+        self.seen.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_duplicates() {
+        let mut deduplicator = Deduplicator::new();
+        let row = EventKey::Row {
+            source: String::from("a.csv"),
+            row: 2,
+            content: 1,
+        };
+
+        assert!(deduplicator.insert(row.clone()));
+        assert!(deduplicator.insert(EventKey::Id(String::from("2"))));
+        assert!(!deduplicator.insert(row));
+        assert!(deduplicator.insert(EventKey::Row {
+            source: String::from("b.csv"),
+            row: 2,
+            content: 1,
+        }));
+        assert!(deduplicator.insert(EventKey::Row {
+            source: String::from("a.csv"),
+            row: 2,
+            content: 2,
+        }));
+    }
+
+    #[test]
+    fn content_hashes_are_stable() {
+        let hash = |fields: &[&str]| content_hash(fields.iter().map(|field| field.as_bytes()));
+
+        assert_eq!(hash(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(
+            hash(&["deposit", "1", "1", "10"]),
+            hash(&["deposit", "1", "1", "10"])
+        );
+        assert_ne!(
+            hash(&["deposit", "1", "1", "10"]),
+            hash(&["deposit", "2", "2", "99"])
+        );
+        assert_ne!(hash(&["a", "bc"]), hash(&["ab", "c"]));
+    }
+}
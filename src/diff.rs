@@ -0,0 +1,214 @@
+use {
+    crate::{input, output, Amount, ClientId},
+    csv::StringRecord,
+    std::{
+        collections::BTreeMap,
+        fmt,
+        io::{self, Read, Write},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+pub enum DiffError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+    #[error("Missing column \"{0}\"")]
+    MissingColumn(&'static str),
+    #[error("Invalid value in column \"{column}\": \"{value}\"")]
+    InvalidValue { column: &'static str, value: String },
+}
+
+/// One row of a final accounts state, as written by the engine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccountState {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// A difference between two accounts states.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    OnlyInLeft(ClientId),
+    OnlyInRight(ClientId),
+    Changed {
+        client: ClientId,
+        left: AccountState,
+        right: AccountState,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OnlyInLeft(client) => write!(f, "< client {}", client),
+            Self::OnlyInRight(client) => write!(f, "> client {}", client),
+            Self::Changed {
+                client,
+                left,
+                right,
+            } => {
+                write!(f, "~ client {}:", client)?;
+                if left.available != right.available {
+                    write!(f, " available {} -> {}", left.available, right.available)?;
+                }
+                if left.held != right.held {
+                    write!(f, " held {} -> {}", left.held, right.held)?;
+                }
+                if left.total != right.total {
+                    write!(f, " total {} -> {}", left.total, right.total)?;
+                }
+                if left.locked != right.locked {
+                    write!(f, " locked {} -> {}", left.locked, right.locked)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads a CSV accounts state, as written by `Engine::write_accounts_state`.
+pub fn read_accounts_state(
+    reader: impl Read,
+) -> Result<BTreeMap<ClientId, AccountState>, DiffError> {
+    let mut reader = input::csv_reader(reader);
+    let headers = reader.headers()?.clone();
+    let column = |name: &'static str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or(DiffError::MissingColumn(name))
+    };
+    let client = column("client")?;
+    let available = column("available")?;
+    let held = column("held")?;
+    let total = column("total")?;
+    let locked = column("locked")?;
+
+    let mut accounts = BTreeMap::new();
+    for record in reader.records() {
+        let record = record?;
+        accounts.insert(
+            parse(&record, client, "client")?,
+            AccountState {
+                available: parse(&record, available, "available")?,
+                held: parse(&record, held, "held")?,
+                total: parse(&record, total, "total")?,
+                locked: parse(&record, locked, "locked")?,
+            },
+        );
+    }
+
+    Ok(accounts)
+}
+
+/// Writes an accounts state as CSV, in the same format as `Engine::write_accounts_state`.
+pub fn write_accounts_state(
+    writer: impl Write,
+    accounts: &BTreeMap<ClientId, AccountState>,
+) -> Result<(), io::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(output::HEADERS)?;
+    for (client, state) in accounts {
+        writer.write_record([
+            client.to_string(),
+            state.available.to_string(),
+            state.held.to_string(),
+            state.total.to_string(),
+            state.locked.to_string(),
+        ])?;
+    }
+    writer.flush()
+}
+
+fn parse<T: FromStr>(
+    record: &StringRecord,
+    index: usize,
+    column: &'static str,
+) -> Result<T, DiffError> {
+    let value = record.get(index).unwrap_or_default();
+    value.parse().map_err(|_| DiffError::InvalidValue {
+        column,
+        value: value.to_owned(),
+    })
+}
+
+/// Compares two accounts states, regardless of the order of their rows. Amounts are compared by
+/// value, so `1.50` and `1.5` are equal.
+pub fn diff(
+    left: &BTreeMap<ClientId, AccountState>,
+    right: &BTreeMap<ClientId, AccountState>,
+) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for (client, left_state) in left {
+        match right.get(client) {
+            None => differences.push(Difference::OnlyInLeft(*client)),
+            Some(right_state) if right_state != left_state => {
+                differences.push(Difference::Changed {
+                    client: *client,
+                    left: *left_state,
+                    right: *right_state,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for client in right.keys() {
+        if !left.contains_key(client) {
+            differences.push(Difference::OnlyInRight(*client));
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_differences() {
+        let left = "\
+            client,available,held,total,locked\n\
+            1,1.50,0,1.50,false\n\
+            2,3,0,3,false\n\
+            3,0,0,0,true\n\
+        ";
+        let right = "\
+            client,available,held,total,locked\n\
+            3,0,0,0,false\n\
+            1,1.5,0.0,1.5,false\n\
+            4,1,0,1,false\n\
+        ";
+
+        let left = read_accounts_state(left.as_bytes()).unwrap();
+        let right = read_accounts_state(right.as_bytes()).unwrap();
+        let differences: Vec<_> = diff(&left, &right)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            differences,
+            vec![
+                "< client 2",
+                "~ client 3: locked true -> false",
+                "> client 4"
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_every_column() {
+        let state = "client,available,held,total\n1,0,0,0\n";
+
+        assert!(matches!(
+            read_accounts_state(state.as_bytes()),
+            Err(DiffError::MissingColumn("locked"))
+        ));
+    }
+}
@@ -1,15 +1,35 @@
 use {
     crate::{
-        account::{Account, AccountError},
-        event::{Event, EventData, EventError},
-        ClientId,
+        account::{
+            Account, AccountError, Checkpoint, Lock, LockReason, ProcessedDeposit,
+            RequestedWithdrawal,
+        },
+        audit::{Auditor, InvariantError},
+        clearing::{ClearingPolicy, ClearingQueue},
+        dedup::{self, Deduplicator, EventKey},
+        event::{
+            self, CustomEvent, CustomKind, Event, EventData, EventError, EventKind, EventType,
+            ParsedEvent, RegistryError, TypeRegistry,
+        },
+        input::{self, Columns, HeaderError, HeaderMapping},
+        ledger::{Ledger, LedgerEntry},
+        limits::LimitConfig,
+        metrics::{self, Gauges, Metrics},
+        observer::{EngineObserver, Observers},
+        output::{Formatter, OutputFormat},
+        risk::RiskRules,
+        snapshot::{Snapshot, SnapshotError, SnapshotWriter},
+        store::{AccountStore, StorageKind},
+        summary::{Statistics, Summary},
+        Amount, ClientId, Moment, TransactionId,
     },
-    csv::{ReaderBuilder, Trim},
+    csv::{ByteRecord, Position},
     log::debug,
     std::{
-        collections::HashMap,
-        convert::TryFrom,
+        fmt,
         io::{self, Read, Write},
+        rc::Rc,
+        time::{Duration, Instant},
     },
     thiserror::Error,
 };
@@ -20,74 +40,883 @@ pub enum EngineError {
     IoError(#[from] io::Error),
     #[error("CSV error: {0}")]
     CsvError(#[from] csv::Error),
+    #[error("Header error: {0}")]
+    HeaderError(#[from] HeaderError),
     #[error("Event error: {0}")]
     EventError(#[from] EventError),
     #[error("Account error: {0}")]
     AccountError(#[from] AccountError),
+    #[error("Registry error: {0}")]
+    RegistryError(#[from] RegistryError),
+    #[error("Invariant violated after {event:?}: {source}")]
+    InvariantViolation {
+        event: Event,
+        source: InvariantError,
+    },
+    #[error("Invariant violated: {0}")]
+    AuditError(#[from] InvariantError),
+    #[error("{location}: {error}")]
+    AtLocation {
+        location: Location,
+        error: Box<EngineError>,
+    },
+}
+
+impl EngineError {
+    fn at(self, source: Option<&str>, line: Option<u64>) -> Self {
+        if source.is_none() && line.is_none() {
+            return self;
+        }
+        Self::AtLocation {
+            location: Location {
+                source: source.map(str::to_owned),
+                line,
+            },
+            error: Box::new(self),
+        }
+    }
+}
+
+/// Where in the input an error happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub source: Option<String>,
+    pub line: Option<u64>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.source, self.line) {
+            (Some(source), Some(line)) => write!(f, "{}, line {}", source, line),
+            (Some(source), None) => write!(f, "{}", source),
+            (None, Some(line)) => write!(f, "line {}", line),
+            (None, None) => write!(f, "unknown location"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Batch aborted by event {index} ({event:?}): {source}")]
+pub struct BatchError {
+    /// Position of the event that failed within the batch.
+    pub index: usize,
+    pub event: Event,
+    pub source: AccountError,
+}
+
+/// How to undo a single event of a batch.
+#[derive(Debug)]
+struct Undo {
+    client: ClientId,
+    /// `None` if the account didn't exist before the event.
+    checkpoint: Option<Checkpoint>,
+    ledger_len: usize,
 }
 
 /// Orchestrates multiple client accounts.
 #[derive(Debug, Default)]
 pub struct Engine {
-    accounts: HashMap<ClientId, Account>,
+    accounts: AccountStore,
+    ledger: Option<Ledger>,
+    auditor: Option<Auditor>,
+    statistics: Statistics,
+    metrics: Option<Metrics>,
+    observers: Observers,
+    deduplicator: Option<Deduplicator>,
+    header_mapping: HeaderMapping,
+    types: TypeRegistry,
+    handlers: Handlers,
+    clearing: ClearingQueue,
+    clock: Moment,
+    /// The clock at the start of the batch in progress, which it is rolled back to on failure.
+    batch_start: Option<Moment>,
+    limits: LimitConfig,
+    risk_rules: RiskRules,
+}
+
+/// Handles events of a custom type, typically by applying built-in events to the engine.
+pub type CustomHandler = dyn Fn(&mut Engine, CustomEvent) -> Result<(), AccountError>;
+
+/// The handler of every custom type, indexed by `CustomKind`.
+#[derive(Default)]
+struct Handlers(Vec<Rc<CustomHandler>>);
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handlers({})", self.0.len())
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
         Self {
-            accounts: HashMap::new(),
+            accounts: AccountStore::default(),
+            ledger: None,
+            auditor: None,
+            statistics: Statistics::default(),
+            metrics: None,
+            observers: Observers::default(),
+            deduplicator: None,
+            header_mapping: HeaderMapping::default(),
+            types: TypeRegistry::default(),
+            handlers: Handlers::default(),
+            clearing: ClearingQueue::default(),
+            clock: Moment::default(),
+            batch_start: None,
+            limits: LimitConfig::default(),
+            risk_rules: RiskRules::default(),
+        }
+    }
+
+    /// Keeps a ledger of every operation applied to each account.
+    pub fn with_ledger(mut self) -> Self {
+        self.ledger = Some(Ledger::new());
+        self
+    }
+
+    /// Stores accounts with the given kind of storage, which is chosen automatically by default.
+    pub fn with_storage(mut self, kind: StorageKind) -> Self {
+        self.accounts = self.accounts.into_kind(kind);
+        self
+    }
+
+    pub fn storage(&self) -> &AccountStore {
+        &self.accounts
+    }
+
+    /// Keeps deposits pending until they clear according to `policy`, rather than making them
+    /// available immediately. Pending funds can't be withdrawn or disputed.
+    pub fn with_clearing(mut self, policy: ClearingPolicy) -> Self {
+        self.clearing = ClearingQueue::new(policy);
+        self
+    }
+
+    pub fn clearing_policy(&self) -> ClearingPolicy {
+        self.clearing.policy()
+    }
+
+    /// Holds every account to the limits of its client. Existing accounts take their new limits
+    /// too.
+    pub fn with_limits(mut self, limits: LimitConfig) -> Self {
+        let clients: Vec<_> = self.accounts.iter().map(|(client, _)| client).collect();
+        for client in clients {
+            if let Some(account) = self.accounts.get_mut(client) {
+                account.set_limits(limits.limits(client));
+            }
+        }
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &LimitConfig {
+        &self.limits
+    }
+
+    /// Locks or freezes accounts automatically once they break any of these rules, recording the
+    /// first rule broken as the reason. Only disputes opened from then on count as recent, while
+    /// deposits disputed before do count towards the dispute ratio.
+    pub fn with_risk_rules(mut self, rules: RiskRules) -> Self {
+        self.risk_rules = rules;
+        let clients: Vec<_> = self.accounts.iter().map(|(client, _)| client).collect();
+        for client in clients {
+            if let Some(account) = self.accounts.get_mut(client) {
+                self.risk_rules.track(account);
+            }
+        }
+        self
+    }
+
+    pub fn risk_rules(&self) -> &RiskRules {
+        &self.risk_rules
+    }
+
+    /// Advances the engine's clock to a timestamp in seconds, clearing the deposits that came due
+    /// by then. `read_events` does this with the timestamp of every row that has one. The clock
+    /// never goes back.
+    pub fn set_time(&mut self, timestamp: u64) {
+        self.clock.time = self.clock.time.max(timestamp);
+        self.clear_due_deposits();
+    }
+
+    /// How many events were handled so far, and the latest time seen.
+    pub fn clock(&self) -> Moment {
+        self.clock
+    }
+
+    fn clear_due_deposits(&mut self) {
+        while let Some((client, transaction_id)) = self.clearing.pop_due(self.clock) {
+            let account = match self.accounts.get_mut(client) {
+                Some(account) => account,
+                None => continue,
+            };
+            match account.clear_deposit(transaction_id) {
+                Ok(()) => {
+                    let amount = account
+                        .processed_deposit(transaction_id)
+                        .map(ProcessedDeposit::amount)
+                        .unwrap_or_default();
+                    self.observers.notify(|observer| {
+                        observer.deposit_cleared(client, transaction_id, amount)
+                    });
+                }
+                Err(e) => debug!("Failed to clear deposit {}: {}", transaction_id, e),
+            }
+        }
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<&Account> {
+        self.accounts.get(client)
+    }
+
+    /// Locks the account of a client, returning whether it was unlocked until now, and notifies
+    /// observers if it was.
+    pub fn lock_account(&mut self, client: ClientId) -> bool {
+        match self.accounts.get_mut(client) {
+            Some(account) if !account.is_locked() => {
+                let lock = Lock::new(LockReason::Admin).with_sequence(self.clock.events);
+                account.set_lock(lock);
+                self.observers
+                    .notify(|observer| observer.account_locked(client, &lock));
+                true
+            }
+            _ => false,
         }
     }
 
+    /// Every known account, in order of client ID when storage is dense and in no particular
+    /// order otherwise.
+    pub fn accounts(&self) -> impl Iterator<Item = (ClientId, &Account)> {
+        self.accounts.iter()
+    }
+
+    /// Every deposit of a client that is currently disputed, with its amount.
+    pub fn open_disputes(&self, client: ClientId) -> Vec<(TransactionId, Amount)> {
+        self.account(client)
+            .map(|account| account.open_disputes().collect())
+            .unwrap_or_default()
+    }
+
+    /// Registers an observer to be notified of every change in engine state.
+    pub fn with_observer(mut self, observer: impl EngineObserver + 'static) -> Self {
+        self.add_observer(observer);
+        self
+    }
+
+    pub fn add_observer(&mut self, observer: impl EngineObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    /// Checks the invariants of the accounts after every event read by `read_events`. This makes
+    /// processing slower, but any regression in the account logic fails the run with the event
+    /// that caused it.
+    pub fn with_audit(mut self) -> Self {
+        let mut auditor = Auditor::new();
+        for (client, account) in self.accounts.iter() {
+            auditor.record_opening(client, account.total_funds());
+        }
+        self.auditor = Some(auditor);
+        self
+    }
+
+    /// Checks the invariants of every account and of the system as a whole.
+    ///
+    /// Always succeeds if auditing wasn't enabled with `with_audit`.
+    pub fn audit(&self) -> Result<(), InvariantError> {
+        match &self.auditor {
+            Some(auditor) => auditor.check_all(self.accounts()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Summarises the run so far, given how long it took.
+    pub fn summary(&self, elapsed: Duration) -> Summary {
+        let mut summary = Summary {
+            statistics: self.statistics.clone(),
+            locked_accounts: 0,
+            total_funds: Amount::default(),
+            held_funds: Amount::default(),
+            elapsed,
+        };
+        for account in self.accounts.values() {
+            if account.is_locked() {
+                summary.locked_accounts += 1;
+            }
+            summary.total_funds = summary.total_funds.saturating_add(account.total_funds());
+            summary.held_funds = summary.held_funds.saturating_add(account.held_funds());
+        }
+        summary
+    }
+
+    /// Measures the latency of every handled event, to be exported with `write_metrics`.
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = Some(Metrics::new());
+        self
+    }
+
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Writes the engine counters and gauges, and event latencies if enabled with
+    /// `with_metrics`, in the Prometheus text exposition format.
+    pub fn write_metrics(&self, mut writer: impl Write) -> Result<(), io::Error> {
+        metrics::write_counter(
+            &mut writer,
+            "engine_rows_read_total",
+            "Number of input rows read.",
+            vec![(None, self.statistics.rows_read)],
+        )?;
+        metrics::write_counter(
+            &mut writer,
+            "engine_events_duplicate_total",
+            "Number of events skipped because they had already been processed.",
+            vec![(None, self.statistics.duplicates)],
+        )?;
+        metrics::write_counter(
+            &mut writer,
+            "engine_events_applied_total",
+            "Number of events applied, by type.",
+            EventKind::ALL.iter().map(|kind| {
+                (
+                    Some(("type", kind.to_string())),
+                    self.statistics.applied(*kind),
+                )
+            }),
+        )?;
+        metrics::write_counter(
+            &mut writer,
+            "engine_events_rejected_total",
+            "Number of events rejected, by error.",
+            self.statistics
+                .rejected
+                .iter()
+                .map(|(variant, count)| (Some(("error", variant.to_string())), *count)),
+        )?;
+
+        let mut gauges = Gauges {
+            accounts: self.accounts.len() as u64,
+            ..Default::default()
+        };
+        for account in self.accounts.values() {
+            if account.is_locked() {
+                gauges.locked_accounts += 1;
+            }
+            gauges.active_disputes += account.dispute_count() as u64;
+            gauges.deposit_history_size += account.deposit_count() as u64;
+        }
+        metrics::write_gauges(&mut writer, gauges)?;
+
+        if let Some(metrics) = &self.metrics {
+            metrics.write_latency(&mut writer)?;
+        }
+
+        writer.flush()
+    }
+
+    fn audit_event(&self, event: Event) -> Result<(), EngineError> {
+        if let (Some(auditor), Some(account)) = (&self.auditor, self.accounts.get(event.client)) {
+            auditor
+                .check_account(event.client, account)
+                .and_then(|()| auditor.check_running_total())
+                .map_err(|source| EngineError::InvariantViolation { event, source })?;
+        }
+        Ok(())
+    }
+
+    /// Handles an event, then clears the deposits that came due once it was counted.
     pub fn handle_event(&mut self, event: Event) -> Result<(), AccountError> {
-        let account = self
-            .accounts
-            .entry(event.client)
-            .or_insert_with(Account::new);
-        match event.data {
-            EventData::Deposit {
-                transaction_id,
+        let result = self.handle_without_clearing(event);
+        self.clear_due_deposits();
+        result
+    }
+
+    fn handle_without_clearing(&mut self, event: Event) -> Result<(), AccountError> {
+        let start = self.metrics.is_some().then(Instant::now);
+        let result = self.process_event(event);
+        self.clock.events += 1;
+        if let (Some(metrics), Some(start)) = (&mut self.metrics, start) {
+            metrics.observe_latency(event.data.kind(), start.elapsed());
+        }
+        result
+    }
+
+    fn process_event(&mut self, event: Event) -> Result<(), AccountError> {
+        let (account, created) = self.accounts.get_or_create(event.client);
+        if created {
+            self.observers
+                .notify(|observer| observer.account_created(event.client));
+            if !self.limits.is_empty() {
+                account.set_limits(self.limits.limits(event.client));
+            }
+            self.risk_rules.track(account);
+        }
+        account.advance(self.clock, self.batch_start.unwrap_or(self.clock));
+        let available_before = account.available_funds();
+        let held_before = account.held_funds();
+        let reserved_before = account.reserved_funds();
+        let pending_before = account.pending_funds();
+        let total_before = account.total_funds();
+        let locked_before = account.is_locked();
+        let frozen_before = account.is_frozen();
+
+        if let Err(e) = apply(account, event.data, self.clearing.policy()) {
+            *self.statistics.rejected.entry(e.variant()).or_default() += 1;
+            self.observers
+                .notify(|observer| observer.event_rejected(&event, &e));
+            return Err(e);
+        }
+        *self
+            .statistics
+            .applied
+            .entry(event.data.kind())
+            .or_default() += 1;
+        let transaction_id = event.data.transaction_id();
+        self.risk_rules.enforce(account, transaction_id);
+        if !locked_before {
+            account.set_lock_sequence(self.clock.events + 1);
+        }
+        if let EventData::Deposit { .. } = event.data {
+            self.clearing.push(event.client, transaction_id, self.clock);
+        }
+        let amount = match event.data {
+            EventData::Deposit { amount, .. }
+            | EventData::Withdrawal { amount, .. }
+            | EventData::WithdrawalRequest { amount, .. } => amount,
+            EventData::WithdrawalSettle { .. } | EventData::WithdrawalCancel { .. } => account
+                .requested_withdrawal(transaction_id)
+                .map(RequestedWithdrawal::amount)
+                .unwrap_or_default(),
+            _ => account
+                .processed_deposit(transaction_id)
+                .map(ProcessedDeposit::amount)
+                .unwrap_or_default(),
+        };
+
+        self.observers.notify(|observer| {
+            observer.event_applied(&event);
+            match event.data.kind() {
+                EventKind::Dispute => observer.dispute_opened(event.client, transaction_id, amount),
+                EventKind::Resolve => {
+                    observer.dispute_resolved(event.client, transaction_id, amount)
+                }
+                EventKind::Chargeback => {
+                    observer.charged_back(event.client, transaction_id, amount)
+                }
+                EventKind::Clear => observer.deposit_cleared(event.client, transaction_id, amount),
+                EventKind::Deposit
+                | EventKind::Withdrawal
+                | EventKind::WithdrawalRequest
+                | EventKind::WithdrawalSettle
+                | EventKind::WithdrawalCancel => {}
+            }
+            if let Some(lock) = account.lock() {
+                if !locked_before && account.is_locked() {
+                    observer.account_locked(event.client, lock);
+                }
+                if !frozen_before && account.is_frozen() {
+                    observer.account_frozen(event.client, lock);
+                }
+            }
+        });
+
+        if let Some(auditor) = &mut self.auditor {
+            auditor.record(
+                event.client,
+                event.data.kind(),
                 amount,
-            } => account.deposit(transaction_id, amount)?,
-            EventData::Withdrawal { amount, .. } => account.withdraw(amount)?,
-            EventData::Dispute { transaction_id } => account.dispute(transaction_id)?,
-            EventData::Resolve { transaction_id } => account.resolve(transaction_id)?,
-            EventData::Chargeback { transaction_id } => account.chargeback(transaction_id)?,
+                total_before,
+                account.total_funds(),
+            );
+        }
+
+        if let Some(ledger) = &mut self.ledger {
+            ledger.record(
+                event.client,
+                LedgerEntry {
+                    transaction_id,
+                    kind: event.data.kind(),
+                    amount,
+                    available_before,
+                    held_before,
+                    reserved_before,
+                    pending_before,
+                    available_after: account.available_funds(),
+                    held_after: account.held_funds(),
+                    reserved_after: account.reserved_funds(),
+                    pending_after: account.pending_funds(),
+                    locked: account.is_locked(),
+                },
+            );
         }
+
         Ok(())
     }
 
-    pub fn read_events(&mut self, reader: impl Read) -> Result<(), EngineError> {
-        let mut reader = ReaderBuilder::new()
-            .has_headers(true)
-            .flexible(true)
-            .trim(Trim::All)
-            .from_reader(reader);
+    /// Applies every event of a batch, or none of them.
+    ///
+    /// If any event fails, every account touched by the batch is restored to its state from
+    /// before the batch and the failing event is returned. Only the parts of each account that an
+    /// event could have changed are saved, so the cost is proportional to the size of the batch
+    /// rather than the size of the engine. Observers will already have been notified of the
+    /// events that were undone, and are then notified of the rollback.
+    ///
+    /// Deposits that come due during a batch only clear once the whole batch has been applied.
+    pub fn apply_batch(
+        &mut self,
+        events: impl IntoIterator<Item = Event>,
+    ) -> Result<(), BatchError> {
+        let mut undo_log = Vec::new();
+        let auditor = self.auditor.clone();
+        let statistics = self.statistics.clone();
+        let clock = self.clock;
+        let clearing = self.clearing.len();
+        self.batch_start = Some(clock);
+
+        for (index, event) in events.into_iter().enumerate() {
+            undo_log.push(Undo {
+                client: event.client,
+                checkpoint: self
+                    .accounts
+                    .get(event.client)
+                    .map(|account| account.checkpoint(event.data.transaction_id())),
+                ledger_len: self
+                    .ledger
+                    .as_ref()
+                    .map_or(0, |ledger| ledger.entries(event.client).len()),
+            });
 
-        for event in reader.records() {
-            if let Err(e) = self.handle_event(Event::try_from(event?)?) {
-                debug!("Failed to handle event: {}", e);
+            if let Err(source) = self.handle_without_clearing(event) {
+                let undone = undo_log.len() - 1;
+                self.rollback(undo_log);
+                self.auditor = auditor;
+                self.statistics = statistics;
+                self.clock = clock;
+                self.batch_start = None;
+                self.clearing.truncate(clearing);
+                *self
+                    .statistics
+                    .rejected
+                    .entry(source.variant())
+                    .or_default() += 1;
+                self.observers
+                    .notify(|observer| observer.batch_rolled_back(undone));
+                return Err(BatchError {
+                    index,
+                    event,
+                    source,
+                });
             }
         }
 
+        self.batch_start = None;
+        self.clear_due_deposits();
         Ok(())
     }
 
-    pub fn write_accounts_state(&self, mut writer: impl Write) -> Result<(), io::Error> {
-        writeln!(writer, "client,available,held,total,locked")?;
+    fn rollback(&mut self, undo_log: Vec<Undo>) {
+        for undo in undo_log.into_iter().rev() {
+            match undo.checkpoint {
+                Some(checkpoint) => {
+                    if let Some(account) = self.accounts.get_mut(undo.client) {
+                        account.restore(checkpoint);
+                    }
+                }
+                None => {
+                    self.accounts.remove(undo.client);
+                }
+            }
+            if let Some(ledger) = &mut self.ledger {
+                ledger.truncate(undo.client, undo.ledger_len);
+            }
+        }
+    }
 
-        for (client, account) in &self.accounts {
-            writeln!(
-                writer,
-                "{},{},{},{},{}",
-                client,
-                account.available_funds(),
-                account.held_funds(),
-                account.total_funds(),
-                account.is_locked(),
-            )?;
+    /// Skips events that the deduplicator has already seen, recording every new one.
+    pub fn with_deduplicator(mut self, deduplicator: Deduplicator) -> Self {
+        self.deduplicator = Some(deduplicator);
+        self
+    }
+
+    pub fn deduplicator(&self) -> Option<&Deduplicator> {
+        self.deduplicator.as_ref()
+    }
+
+    /// Writes the state of every account, along with the events processed so far if duplicates
+    /// are skipped, for a later run to resume from with `load_snapshot`.
+    pub fn save_snapshot(&self, writer: impl Write) -> Result<(), io::Error> {
+        let mut snapshot = SnapshotWriter::new(writer);
+        snapshot.clock(self.clock)?;
+        for key in self.deduplicator.iter().flat_map(Deduplicator::keys) {
+            snapshot.key(key)?;
+        }
+        for (client, account) in self.accounts.iter() {
+            snapshot.account(client, account)?;
+        }
+        for (due, client, transaction_id) in self.clearing.deposits() {
+            snapshot.clearing(due, client, transaction_id)?;
+        }
+        snapshot.finish()
+    }
+
+    /// Resumes from a snapshot written by `save_snapshot`, which is only possible before any
+    /// events have been handled.
+    ///
+    /// Limits, risk rules and the clearing policy must be set first, and should be the same as in
+    /// the run that saved the snapshot, since only the withdrawals and disputes that they kept
+    /// track of then are restored. The events already processed are only restored if duplicates
+    /// are skipped.
+    pub fn load_snapshot(&mut self, reader: impl Read) -> Result<(), SnapshotError> {
+        if !self.accounts.is_empty() || self.clock != Moment::default() {
+            return Err(SnapshotError::EngineNotEmpty);
+        }
+        let snapshot = Snapshot::read(reader)?;
+
+        for (client, account) in snapshot.accounts {
+            if let Some(auditor) = &mut self.auditor {
+                auditor.record_opening(client, account.total_funds());
+            }
+            let (slot, _) = self.accounts.get_or_create(client);
+            *slot = account;
+            if !self.limits.is_empty() {
+                slot.set_limits(self.limits.limits(client));
+            }
+            self.risk_rules.track(slot);
+        }
+        for (client, then, amount) in snapshot.withdrawn {
+            if let Some(account) = self.accounts.get_mut(client) {
+                account.restore_recent_withdrawal(then, amount);
+            }
+        }
+        for (client, then) in snapshot.disputed {
+            if let Some(account) = self.accounts.get_mut(client) {
+                account.restore_recent_dispute(then);
+            }
+        }
+        for (due, client, transaction_id) in snapshot.clearing {
+            self.clearing.restore(due, client, transaction_id);
+        }
+        if let Some(deduplicator) = &mut self.deduplicator {
+            for key in snapshot.keys {
+                deduplicator.insert(key);
+            }
+        }
+        self.clock = snapshot.clock;
+        Ok(())
+    }
+
+    /// Resolves the columns of every input from its headers with this mapping.
+    pub fn with_header_mapping(mut self, header_mapping: HeaderMapping) -> Self {
+        self.header_mapping = header_mapping;
+        self
+    }
+
+    /// Registers a custom event type, read from inputs under `name` and handled by `handler`.
+    ///
+    /// Handlers are called with the engine itself, so they can apply built-in events with
+    /// `handle_event`, which keeps statistics, observers and the ledger up to date. Events of
+    /// custom types are only audited at the end of a run, and rejections are only logged.
+    pub fn register_event_type(
+        &mut self,
+        name: &str,
+        handler: impl Fn(&mut Engine, CustomEvent) -> Result<(), AccountError> + 'static,
+    ) -> Result<CustomKind, RegistryError> {
+        let kind = self.types.register(name)?;
+        self.handlers.0.push(Rc::new(handler));
+        Ok(kind)
+    }
+
+    /// Also reads events of a type under another name, such as `payout` for `withdrawal`.
+    pub fn add_type_alias(
+        &mut self,
+        alias: &str,
+        event_type: EventType,
+    ) -> Result<(), RegistryError> {
+        self.types.add_alias(alias, event_type)
+    }
+
+    pub fn types(&self) -> &TypeRegistry {
+        &self.types
+    }
+
+    /// Handles an event of a custom type with the handler it was registered with. Fails if its
+    /// type was registered with another engine.
+    pub fn handle_custom_event(&mut self, event: CustomEvent) -> Result<(), EngineError> {
+        if !self.types.contains(event.kind) {
+            return Err(RegistryError::UnknownCustomKind(event.kind).into());
+        }
+        let handler = Rc::clone(&self.handlers.0[event.kind.index]);
+        handler(self, event)?;
+        Ok(())
+    }
+
+    pub fn read_events(&mut self, reader: impl Read) -> Result<(), EngineError> {
+        self.read_source(None, reader)
+    }
+
+    /// Reads events from a source with a stable name, such as the path of a file. Errors are
+    /// reported with the name of the source and the line they happened on.
+    ///
+    /// Columns are found by their headers, so invalid headers fail before any event is read. The
+    /// `timestamp` column is only read if deposits clear after a number of seconds, or a limit is
+    /// over a window in seconds.
+    ///
+    /// When deduplicating, events are identified by their `event_id` column if the input has one,
+    /// otherwise by the name of the source, their row number and a hash of their fields. Events
+    /// from unnamed sources without an `event_id` are never considered duplicates.
+    pub fn read_source(
+        &mut self,
+        source: Option<&str>,
+        reader: impl Read,
+    ) -> Result<(), EngineError> {
+        let mut reader = input::untrimmed_csv_reader(reader);
+        let headers = reader.headers()?;
+        let timestamps = self.clearing.policy().uses_timestamps() || self.limits.uses_timestamps();
+        let columns = self
+            .header_mapping
+            .resolve_with_timestamps(headers, timestamps)
+            .map_err(|e| {
+                let line = headers.position().map(Position::line);
+                EngineError::from(e).at(source, line)
+            })?;
+
+        // A single record is reused for every row, so that valid rows don't allocate.
+        let mut record = ByteRecord::new();
+        loop {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    let line = e.position().map(Position::line);
+                    return Err(EngineError::from(e).at(source, line));
+                }
+            }
+            let line = record.position().map(Position::line);
+            self.statistics.rows_read += 1;
+            self.read_record(source, &columns, &record)
+                .map_err(|e| e.at(source, line))?;
         }
 
         Ok(())
     }
+
+    fn read_record(
+        &mut self,
+        source: Option<&str>,
+        columns: &Columns,
+        record: &ByteRecord,
+    ) -> Result<(), EngineError> {
+        let event = self.types.parse(record, columns)?;
+        if let Some(timestamp) = event::parse_timestamp(record, columns)? {
+            self.set_time(timestamp);
+        }
+
+        if self.is_duplicate(source, columns, record) {
+            debug!("Skipping duplicate event: {:?}", event);
+            self.statistics.duplicates += 1;
+            if let ParsedEvent::Builtin(event) = &event {
+                self.observers
+                    .notify(|observer| observer.duplicate_skipped(event));
+            }
+            return Ok(());
+        }
+
+        match event {
+            ParsedEvent::Builtin(event) => {
+                if let Err(e) = self.handle_event(event) {
+                    debug!("Failed to handle event: {}", e);
+                }
+                self.audit_event(event)
+            }
+            ParsedEvent::Custom(event) => {
+                if let Err(e) = self.handle_custom_event(event) {
+                    debug!("Failed to handle event: {}", e);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the event of a row with the deduplicator, if there is one, and returns whether it
+    /// had already been seen.
+    fn is_duplicate(
+        &mut self,
+        source: Option<&str>,
+        columns: &Columns,
+        record: &ByteRecord,
+    ) -> bool {
+        let deduplicator = match &mut self.deduplicator {
+            Some(deduplicator) => deduplicator,
+            None => return false,
+        };
+        let key = match columns
+            .event_id
+            .and_then(|i| record.get(i))
+            .map(event::trim)
+        {
+            Some(id) if !id.is_empty() => {
+                Some(EventKey::Id(String::from_utf8_lossy(id).into_owned()))
+            }
+            _ => source
+                .zip(record.position())
+                .map(|(source, position)| EventKey::Row {
+                    source: source.to_owned(),
+                    row: position.line(),
+                    content: dedup::content_hash(record.iter().map(event::trim)),
+                }),
+        };
+        key.is_some_and(|key| !deduplicator.insert(key))
+    }
+
+    pub fn write_accounts_state(&self, writer: impl Write) -> Result<(), io::Error> {
+        self.write_accounts(OutputFormat::Csv.formatter(writer).as_mut())
+    }
+
+    pub fn write_accounts(&self, formatter: &mut dyn Formatter) -> Result<(), io::Error> {
+        formatter.begin()?;
+        for (client, account) in self.accounts.iter() {
+            formatter.write_account(client, account)?;
+        }
+        formatter.finish()
+    }
+}
+
+fn apply(
+    account: &mut Account,
+    data: EventData,
+    clearing: ClearingPolicy,
+) -> Result<(), AccountError> {
+    match data {
+        EventData::Deposit {
+            transaction_id,
+            amount,
+        } if clearing == ClearingPolicy::Immediate => account.deposit(transaction_id, amount)?,
+        EventData::Deposit {
+            transaction_id,
+            amount,
+        } => account.deposit_pending(transaction_id, amount)?,
+        EventData::Withdrawal { amount, .. } => account.withdraw(amount)?,
+        EventData::Dispute { transaction_id } => account.dispute(transaction_id)?,
+        EventData::Resolve { transaction_id } => account.resolve(transaction_id)?,
+        EventData::Chargeback { transaction_id } => account.chargeback(transaction_id)?,
+        EventData::WithdrawalRequest {
+            transaction_id,
+            amount,
+        } => account.request_withdrawal(transaction_id, amount)?,
+        EventData::WithdrawalSettle { transaction_id } => {
+            account.settle_withdrawal(transaction_id)?
+        }
+        EventData::WithdrawalCancel { transaction_id } => {
+            account.cancel_withdrawal(transaction_id)?
+        }
+        EventData::Clear { transaction_id } => account.clear_deposit(transaction_id)?,
+    }
+    Ok(())
 }
@@ -1,7 +1,17 @@
 use {
-    crate::{Amount, ClientId, TransactionId},
-    csv::StringRecord,
-    std::{convert::TryFrom, num::ParseIntError},
+    crate::{
+        input::{Columns, Field, Strictness},
+        Amount, ClientId, TransactionId,
+    },
+    csv::{ByteRecord, StringRecord},
+    derive_more::Display,
+    rust_decimal::Decimal,
+    std::{
+        convert::TryFrom,
+        num::ParseIntError,
+        str::FromStr,
+        sync::atomic::{AtomicU64, Ordering},
+    },
     thiserror::Error,
 };
 
@@ -10,6 +20,20 @@ const WITHDRAWAL: &str = "withdrawal";
 const DISPUTE: &str = "dispute";
 const RESOLVE: &str = "resolve";
 const CHARGEBACK: &str = "chargeback";
+const WITHDRAWAL_REQUEST: &str = "withdrawal_request";
+const WITHDRAWAL_SETTLE: &str = "withdrawal_settle";
+const WITHDRAWAL_CANCEL: &str = "withdrawal_cancel";
+const CLEAR: &str = "clear";
+
+/// IDs with more digits than this are left to `str::parse`, which reports the exact error.
+const MAX_ID_DIGITS: usize = 10;
+
+/// Amounts with up to this many digits fit in the `i64` mantissa of `Decimal::new`.
+const MAX_FAST_AMOUNT_DIGITS: usize = 18;
+
+/// The ID of the next registry to register a custom type, so that custom types of different
+/// registries can be told apart. Registries without custom types don't need one.
+static NEXT_REGISTRY: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Debug, Error)]
 pub enum EventError {
@@ -29,6 +53,76 @@ pub enum EventError {
     InvalidTransactionId(ParseIntError),
     #[error("Error parsing amount: {0}")]
     InvalidAmount(rust_decimal::Error),
+    #[error("Error parsing timestamp: {0}")]
+    InvalidTimestamp(ParseIntError),
+    #[error("Expected at most {expected} fields, found {found}")]
+    TooManyFields { expected: usize, found: usize },
+    #[error("Empty required field \"{0}\"")]
+    EmptyField(Field),
+    #[error("Unexpected amount for a {0}")]
+    UnexpectedAmount(EventKind),
+    #[error("Type must be written exactly as \"{expected}\", got \"{found}\"")]
+    NonCanonicalType { expected: String, found: String },
+}
+
+/// The type of an event, without any of its data.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    #[display(fmt = "{}", DEPOSIT)]
+    Deposit,
+    #[display(fmt = "{}", WITHDRAWAL)]
+    Withdrawal,
+    #[display(fmt = "{}", DISPUTE)]
+    Dispute,
+    #[display(fmt = "{}", RESOLVE)]
+    Resolve,
+    #[display(fmt = "{}", CHARGEBACK)]
+    Chargeback,
+    #[display(fmt = "{}", WITHDRAWAL_REQUEST)]
+    WithdrawalRequest,
+    #[display(fmt = "{}", WITHDRAWAL_SETTLE)]
+    WithdrawalSettle,
+    #[display(fmt = "{}", WITHDRAWAL_CANCEL)]
+    WithdrawalCancel,
+    #[display(fmt = "{}", CLEAR)]
+    Clear,
+}
+
+impl EventKind {
+    pub const ALL: [Self; 9] = [
+        Self::Deposit,
+        Self::Withdrawal,
+        Self::Dispute,
+        Self::Resolve,
+        Self::Chargeback,
+        Self::WithdrawalRequest,
+        Self::WithdrawalSettle,
+        Self::WithdrawalCancel,
+        Self::Clear,
+    ];
+
+    /// The name of the type in the input format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Deposit => DEPOSIT,
+            Self::Withdrawal => WITHDRAWAL,
+            Self::Dispute => DISPUTE,
+            Self::Resolve => RESOLVE,
+            Self::Chargeback => CHARGEBACK,
+            Self::WithdrawalRequest => WITHDRAWAL_REQUEST,
+            Self::WithdrawalSettle => WITHDRAWAL_SETTLE,
+            Self::WithdrawalCancel => WITHDRAWAL_CANCEL,
+            Self::Clear => CLEAR,
+        }
+    }
+
+    /// Whether events of this type have an amount.
+    pub fn has_amount(self) -> bool {
+        matches!(
+            self,
+            Self::Deposit | Self::Withdrawal | Self::WithdrawalRequest
+        )
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -50,6 +144,53 @@ pub enum EventData {
     Chargeback {
         transaction_id: TransactionId,
     },
+    /// First phase of a withdrawal, which reserves the funds.
+    WithdrawalRequest {
+        transaction_id: TransactionId,
+        amount: Amount,
+    },
+    /// Pays out the funds reserved by a withdrawal request.
+    WithdrawalSettle {
+        transaction_id: TransactionId,
+    },
+    /// Returns the funds reserved by a withdrawal request.
+    WithdrawalCancel {
+        transaction_id: TransactionId,
+    },
+    /// Makes the funds of a pending deposit available.
+    Clear {
+        transaction_id: TransactionId,
+    },
+}
+
+impl EventData {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Deposit { .. } => EventKind::Deposit,
+            Self::Withdrawal { .. } => EventKind::Withdrawal,
+            Self::Dispute { .. } => EventKind::Dispute,
+            Self::Resolve { .. } => EventKind::Resolve,
+            Self::Chargeback { .. } => EventKind::Chargeback,
+            Self::WithdrawalRequest { .. } => EventKind::WithdrawalRequest,
+            Self::WithdrawalSettle { .. } => EventKind::WithdrawalSettle,
+            Self::WithdrawalCancel { .. } => EventKind::WithdrawalCancel,
+            Self::Clear { .. } => EventKind::Clear,
+        }
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        match *self {
+            Self::Deposit { transaction_id, .. }
+            | Self::Withdrawal { transaction_id, .. }
+            | Self::Dispute { transaction_id }
+            | Self::Resolve { transaction_id }
+            | Self::Chargeback { transaction_id }
+            | Self::WithdrawalRequest { transaction_id, .. }
+            | Self::WithdrawalSettle { transaction_id }
+            | Self::WithdrawalCancel { transaction_id }
+            | Self::Clear { transaction_id } => transaction_id,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -62,37 +203,478 @@ impl TryFrom<StringRecord> for Event {
     type Error = EventError;
 
     fn try_from(event: StringRecord) -> Result<Self, Self::Error> {
-        let event_type = event.get(0).ok_or(EventError::MissingType)?;
-        let client = event
-            .get(1)
-            .ok_or(EventError::MissingClientId)?
-            .parse()
-            .map_err(EventError::InvalidClientId)?;
-        let transaction_id = event
-            .get(2)
-            .ok_or(EventError::MissingTransactionId)?
-            .parse()
-            .map_err(EventError::InvalidTransactionId)?;
-        let amount = event
-            .get(3)
-            .map(|x| x.parse().map_err(EventError::InvalidAmount));
-
-        let data = match (event_type, amount) {
-            (DEPOSIT, None) | (WITHDRAWAL, None) => return Err(EventError::MissingAmount),
-            (DEPOSIT, Some(amount)) => EventData::Deposit {
+        Self::try_from(event.as_byte_record())
+    }
+}
+
+impl TryFrom<&ByteRecord> for Event {
+    type Error = EventError;
+
+    /// Parses a record with its columns in the order of the input format.
+    fn try_from(event: &ByteRecord) -> Result<Self, Self::Error> {
+        Self::from_record(event, &Columns::default())
+    }
+}
+
+impl Event {
+    /// Parses a record with only the built-in types and their aliases.
+    pub fn from_record(event: &ByteRecord, columns: &Columns) -> Result<Self, EventError> {
+        match TypeRegistry::default().parse(event, columns)? {
+            ParsedEvent::Builtin(event) => Ok(event),
+            ParsedEvent::Custom(_) => unreachable!("the default registry has no custom types"),
+        }
+    }
+}
+
+/// A type of event registered by a library user rather than built into the engine. Only means
+/// something to the registry it was registered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomKind {
+    registry: u64,
+    pub(crate) index: usize,
+}
+
+/// The type an event type name stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    Builtin(EventKind),
+    Custom(CustomKind),
+}
+
+/// An event of a custom type, handled by the handler it was registered with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CustomEvent {
+    pub kind: CustomKind,
+    pub client: ClientId,
+    pub transaction_id: TransactionId,
+    /// The amount, if the row had a non-empty one.
+    pub amount: Option<Amount>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParsedEvent {
+    Builtin(Event),
+    Custom(CustomEvent),
+}
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("Type \"{0}\" is already registered")]
+    AlreadyRegistered(String),
+    #[error("Invalid type name: \"{0}\"")]
+    InvalidName(String),
+    #[error("Custom type {0:?} isn't registered with this engine")]
+    UnknownCustomKind(CustomKind),
+}
+
+/// Every name a built-in type can be written as, other than the names of extra aliases.
+const BUILTIN_TYPES: [(&str, EventKind); 11] = [
+    (DEPOSIT, EventKind::Deposit),
+    (WITHDRAWAL, EventKind::Withdrawal),
+    ("withdraw", EventKind::Withdrawal),
+    (DISPUTE, EventKind::Dispute),
+    (RESOLVE, EventKind::Resolve),
+    (CHARGEBACK, EventKind::Chargeback),
+    ("cb", EventKind::Chargeback),
+    (WITHDRAWAL_REQUEST, EventKind::WithdrawalRequest),
+    (WITHDRAWAL_SETTLE, EventKind::WithdrawalSettle),
+    (WITHDRAWAL_CANCEL, EventKind::WithdrawalCancel),
+    (CLEAR, EventKind::Clear),
+];
+
+/// The names event types can be written as in the input. Names are matched regardless of case,
+/// except in strict mode, where they must be written exactly as registered.
+///
+/// Besides the built-in types and their aliases, such as `withdraw` and `cb`, any number of
+/// aliases and custom types can be registered.
+#[derive(Debug, Default, Clone)]
+pub struct TypeRegistry {
+    /// Set when the first custom type is registered.
+    id: u64,
+    names: Vec<(String, EventType)>,
+    custom: Vec<String>,
+}
+
+impl TypeRegistry {
+    /// Finds the type with this name, regardless of case, along with the name it was registered
+    /// under.
+    pub fn find(&self, name: &[u8]) -> Option<(&str, EventType)> {
+        // Built-in names written exactly are by far the most common, and the cheapest to match.
+        if let Some((name, kind)) = BUILTIN_TYPES
+            .iter()
+            .find(|(builtin, _)| builtin.as_bytes() == name)
+        {
+            return Some((name, EventType::Builtin(*kind)));
+        }
+        let builtin = BUILTIN_TYPES
+            .iter()
+            .map(|(name, kind)| (*name, EventType::Builtin(*kind)));
+        let registered = self
+            .names
+            .iter()
+            .map(|(name, event_type)| (name.as_str(), *event_type));
+        builtin
+            .chain(registered)
+            .find(|(registered, _)| registered.as_bytes().eq_ignore_ascii_case(name))
+    }
+
+    /// The name a custom type was registered under.
+    ///
+    /// Panics if it was registered with another registry.
+    pub fn name(&self, kind: CustomKind) -> &str {
+        assert!(self.contains(kind), "{:?} isn't registered here", kind);
+        &self.custom[kind.index]
+    }
+
+    /// Whether a custom type was registered with this registry.
+    pub fn contains(&self, kind: CustomKind) -> bool {
+        self.id != 0 && kind.registry == self.id && kind.index < self.custom.len()
+    }
+
+    /// Also accepts `alias` as the name of a type.
+    pub fn add_alias(&mut self, alias: &str, event_type: EventType) -> Result<(), RegistryError> {
+        if alias.is_empty() || alias.trim() != alias || alias.contains(',') {
+            return Err(RegistryError::InvalidName(alias.to_owned()));
+        }
+        if self.find(alias.as_bytes()).is_some() {
+            return Err(RegistryError::AlreadyRegistered(alias.to_owned()));
+        }
+        self.names.push((alias.to_owned(), event_type));
+        Ok(())
+    }
+
+    /// Registers a new custom type.
+    pub fn register(&mut self, name: &str) -> Result<CustomKind, RegistryError> {
+        if self.id == 0 {
+            self.id = NEXT_REGISTRY.fetch_add(1, Ordering::Relaxed);
+        }
+        let kind = CustomKind {
+            registry: self.id,
+            index: self.custom.len(),
+        };
+        self.add_alias(name, EventType::Custom(kind))?;
+        self.custom.push(name.to_owned());
+        Ok(kind)
+    }
+
+    /// Parses the fields straight from bytes, without allocating for valid events. Fields don't
+    /// need to have been trimmed already.
+    pub fn parse(&self, event: &ByteRecord, columns: &Columns) -> Result<ParsedEvent, EventError> {
+        if columns.strictness == Strictness::Strict {
+            self.check_shape(event, columns)?;
+        }
+
+        let field = |i| event.get(i).map(trim);
+        let event_type = field(columns.kind).ok_or(EventError::MissingType)?;
+        let client = parse_id::<u16>(field(columns.client).ok_or(EventError::MissingClientId)?)
+            .map_err(EventError::InvalidClientId)?
+            .into();
+        let transaction_id =
+            field(columns.transaction_id).ok_or(EventError::MissingTransactionId)?;
+        let transaction_id = parse_id::<u32>(transaction_id)
+            .map_err(EventError::InvalidTransactionId)?
+            .into();
+        let amount = field(columns.amount);
+
+        let kind = match self.find(event_type) {
+            Some((_, EventType::Builtin(kind))) => kind,
+            Some((_, EventType::Custom(kind))) => {
+                return Ok(ParsedEvent::Custom(CustomEvent {
+                    kind,
+                    client,
+                    transaction_id,
+                    amount: amount
+                        .filter(|amount| !amount.is_empty())
+                        .map(parse_amount)
+                        .transpose()?,
+                }))
+            }
+            None => {
+                let unknown = String::from_utf8_lossy(event_type).into_owned();
+                return Err(EventError::UnknownType(unknown));
+            }
+        };
+
+        let amount = || amount.map_or(Err(EventError::MissingAmount), parse_amount);
+        let data = match kind {
+            EventKind::Deposit => EventData::Deposit {
                 transaction_id,
-                amount: amount?,
+                amount: amount()?,
             },
-            (WITHDRAWAL, Some(amount)) => EventData::Withdrawal {
+            EventKind::Withdrawal => EventData::Withdrawal {
                 transaction_id,
-                amount: amount?,
+                amount: amount()?,
             },
-            (DISPUTE, _) => EventData::Dispute { transaction_id },
-            (RESOLVE, _) => EventData::Resolve { transaction_id },
-            (CHARGEBACK, _) => EventData::Chargeback { transaction_id },
-            (unknown, _) => return Err(EventError::UnknownType(unknown.to_owned())),
+            EventKind::Dispute => EventData::Dispute { transaction_id },
+            EventKind::Resolve => EventData::Resolve { transaction_id },
+            EventKind::Chargeback => EventData::Chargeback { transaction_id },
+            EventKind::WithdrawalRequest => EventData::WithdrawalRequest {
+                transaction_id,
+                amount: amount()?,
+            },
+            EventKind::WithdrawalSettle => EventData::WithdrawalSettle { transaction_id },
+            EventKind::WithdrawalCancel => EventData::WithdrawalCancel { transaction_id },
+            EventKind::Clear => EventData::Clear { transaction_id },
+        };
+
+        Ok(ParsedEvent::Builtin(Event { client, data }))
+    }
+
+    /// Checks that a row has exactly the fields its type requires, and its type is written
+    /// exactly as registered. Anything else wrong with the row is left to parsing.
+    fn check_shape(&self, event: &ByteRecord, columns: &Columns) -> Result<(), EventError> {
+        if event.len() > columns.len {
+            return Err(EventError::TooManyFields {
+                expected: columns.len,
+                found: event.len(),
+            });
+        }
+        let field = |i| event.get(i).map(trim);
+        for (column, name) in [
+            (columns.kind, Field::Type),
+            (columns.client, Field::Client),
+            (columns.transaction_id, Field::TransactionId),
+        ] {
+            if field(column).is_some_and(<[u8]>::is_empty) {
+                return Err(EventError::EmptyField(name));
+            }
+        }
+
+        let event_type = event.get(columns.kind).unwrap_or_default();
+        let kind = match self.find(trim(event_type)) {
+            Some((name, _)) if name.as_bytes() != event_type => {
+                return Err(EventError::NonCanonicalType {
+                    expected: name.to_owned(),
+                    found: String::from_utf8_lossy(event_type).into_owned(),
+                })
+            }
+            Some((_, EventType::Builtin(kind))) => kind,
+            Some((_, EventType::Custom(_))) | None => return Ok(()),
+        };
+
+        match field(columns.amount) {
+            Some(amount) if amount.is_empty() && kind.has_amount() => {
+                Err(EventError::EmptyField(Field::Amount))
+            }
+            Some(amount) if !amount.is_empty() && !kind.has_amount() => {
+                Err(EventError::UnexpectedAmount(kind))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses the timestamp of a row, in seconds, if the input has a timestamp column and the row has
+/// a value in it.
+pub fn parse_timestamp(record: &ByteRecord, columns: &Columns) -> Result<Option<u64>, EventError> {
+    match columns.timestamp.and_then(|i| record.get(i)).map(trim) {
+        Some(timestamp) if !timestamp.is_empty() => parse_id(timestamp)
+            .map(Some)
+            .map_err(EventError::InvalidTimestamp),
+        _ => Ok(None),
+    }
+}
+
+/// Trims whitespace around a field, exactly like `str::trim`. Fields are almost always ASCII, so
+/// the field is only decoded when what's left after trimming ASCII whitespace doesn't start and end
+/// with a visible ASCII character.
+pub(crate) fn trim(field: &[u8]) -> &[u8] {
+    let trimmed = field.trim_ascii();
+    match (trimmed.first(), trimmed.last()) {
+        (Some(first), Some(last)) if first.is_ascii_graphic() && last.is_ascii_graphic() => trimmed,
+        _ => std::str::from_utf8(field).map_or(trimmed, |field| field.trim().as_bytes()),
+    }
+}
+
+/// Parses an ID made of plain ASCII digits, falling back to `str::parse` for anything else so that
+/// the result, or the error, is exactly the same.
+fn parse_id<T>(bytes: &[u8]) -> Result<T, ParseIntError>
+where
+    T: TryFrom<u64> + FromStr<Err = ParseIntError>,
+{
+    if !bytes.is_empty() && bytes.len() <= MAX_ID_DIGITS {
+        let digits = bytes.iter().try_fold(0u64, |value, byte| {
+            let digit = byte.wrapping_sub(b'0');
+            (digit <= 9).then(|| value * 10 + u64::from(digit))
+        });
+        if let Some(Ok(id)) = digits.map(T::try_from) {
+            return Ok(id);
+        }
+    }
+    String::from_utf8_lossy(bytes).parse()
+}
+
+fn parse_amount(bytes: &[u8]) -> Result<Amount, EventError> {
+    match parse_decimal(bytes) {
+        Some(amount) => Ok(amount.into()),
+        None => String::from_utf8_lossy(bytes)
+            .parse()
+            .map_err(EventError::InvalidAmount),
+    }
+}
+
+/// Parses a plain decimal such as `-12.3400` into the same value and scale as `Decimal::from_str`
+/// would. Returns `None` for anything else, such as exponents, underscores, negative zero or too
+/// many digits, which is left to `Decimal::from_str`.
+fn parse_decimal(bytes: &[u8]) -> Option<Decimal> {
+    let (negative, bytes) = match bytes.split_first()? {
+        (b'-', rest) => (true, rest),
+        _ => (false, bytes),
+    };
+    let (integer, fraction) = match bytes.iter().position(|byte| *byte == b'.') {
+        Some(point) if point + 1 < bytes.len() => (&bytes[..point], &bytes[point + 1..]),
+        Some(_) => return None,
+        None => (bytes, &[][..]),
+    };
+    if integer.is_empty() || integer.len() + fraction.len() > MAX_FAST_AMOUNT_DIGITS {
+        return None;
+    }
+
+    let mantissa = integer
+        .iter()
+        .chain(fraction)
+        .try_fold(0i64, |value, byte| {
+            let digit = byte.wrapping_sub(b'0');
+            (digit <= 9).then(|| value * 10 + i64::from(digit))
+        })?;
+    if negative && mantissa == 0 {
+        return None;
+    }
+    let mantissa = if negative { -mantissa } else { mantissa };
+    Some(Decimal::new(mantissa, fraction.len() as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_paths_match_standard_parsers() {
+        let amounts = [
+            "0",
+            "1",
+            "1.5",
+            "1.50",
+            "0.0001",
+            "-2.25",
+            "0.000",
+            "007.10",
+            "123456789.123456789",
+            "1234567890123456789",
+            "-0",
+            "-0.0",
+            "+1",
+            "1.",
+            ".5",
+            "1e3",
+            "1_000",
+            "",
+            "-",
+            "1.2.3",
+            "٣",
+            "79228162514264337593543950335",
+            "0.0000000000000000000000000001",
+        ];
+        for amount in amounts.iter() {
+            let fast = parse_amount(amount.as_bytes());
+            let standard = amount.parse::<Amount>();
+            match (fast, standard) {
+                (Ok(fast), Ok(standard)) => {
+                    assert_eq!(fast.to_string(), standard.to_string(), "{}", amount)
+                }
+                (Err(EventError::InvalidAmount(_)), Err(_)) => {}
+                (fast, standard) => panic!("{}: {:?} != {:?}", amount, fast, standard),
+            }
+        }
+
+        let ids = [
+            "0",
+            "1",
+            "65535",
+            "65536",
+            "+7",
+            "-1",
+            "",
+            "00001",
+            "99999999999",
+            "1 ",
+        ];
+        for id in ids.iter() {
+            assert_eq!(parse_id::<u16>(id.as_bytes()), id.parse::<u16>(), "{}", id);
+            assert_eq!(parse_id::<u32>(id.as_bytes()), id.parse::<u32>(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn trims_like_str_trim() {
+        let fields = [
+            "",
+            " ",
+            "deposit",
+            "  10.5\t",
+            "\u{a0}deposit\u{a0}",
+            "\u{3000}1\u{2003}",
+            " \u{b}7\r",
+            "é ",
+            "a b",
+        ];
+        for field in fields.iter() {
+            assert_eq!(
+                trim(field.as_bytes()),
+                field.trim().as_bytes(),
+                "{:?}",
+                field
+            );
+        }
+        assert_eq!(trim(b" \xff "), b"\xff");
+
+        let columns = Columns::default();
+        let record = ByteRecord::from(vec!["\u{a0}deposit", "1\u{a0}", "\u{3000}2", "3.5\u{a0}"]);
+        assert_eq!(
+            Event::from_record(&record, &columns).unwrap(),
+            Event {
+                client: 1.into(),
+                data: EventData::Deposit {
+                    transaction_id: 2.into(),
+                    amount: "3.5".parse().unwrap(),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn types_are_found_regardless_of_case() {
+        let mut types = TypeRegistry::default();
+        let refund = types.register("Refund").unwrap();
+        types
+            .add_alias("payout", EventType::Builtin(EventKind::Withdrawal))
+            .unwrap();
+
+        let find = |name: &str| {
+            types
+                .find(name.as_bytes())
+                .map(|(_, event_type)| event_type)
         };
+        let builtin = |kind| Some(EventType::Builtin(kind));
+        assert_eq!(find("DEPOSIT"), builtin(EventKind::Deposit));
+        assert_eq!(find("Withdraw"), builtin(EventKind::Withdrawal));
+        assert_eq!(find("PayOut"), builtin(EventKind::Withdrawal));
+        assert_eq!(find("CB"), builtin(EventKind::Chargeback));
+        assert_eq!(find("refund"), Some(EventType::Custom(refund)));
+        assert_eq!(find("transfer"), None);
+        assert_eq!(types.name(refund), "Refund");
 
-        Ok(Self { client, data })
+        assert!(matches!(
+            types.register("REFUND"),
+            Err(RegistryError::AlreadyRegistered(_))
+        ));
+        assert!(matches!(
+            types.add_alias("Chargeback", EventType::Custom(refund)),
+            Err(RegistryError::AlreadyRegistered(_))
+        ));
+        assert!(matches!(
+            types.register(" fee"),
+            Err(RegistryError::InvalidName(_))
+        ));
     }
 }
@@ -0,0 +1,428 @@
+use {
+    crate::{
+        diff::AccountState,
+        event::{Event, EventData},
+        Amount, ClientId, TransactionId,
+    },
+    rand::{Rng, SeedableRng},
+    rand_chacha::ChaCha8Rng,
+    rust_decimal::Decimal,
+    std::{
+        collections::{BTreeMap, HashMap},
+        convert::TryFrom,
+        fmt,
+        io::{self, Write},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+const HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Chance of continuing the lifecycle of an earlier deposit, rather than starting a new
+/// transaction, whenever one is waiting.
+const LIFECYCLE_PROBABILITY: f64 = 0.5;
+
+/// Amounts are generated with the four decimal places the input format allows.
+const AMOUNT_SCALE: u32 = 4;
+
+#[derive(Debug, Error)]
+pub enum GeneratorError {
+    #[error("{name} must be between 0 and 1, got {value}")]
+    InvalidRate { name: &'static str, value: f64 },
+    #[error("Client count must be at least 1")]
+    NoClients,
+    #[error("Can't generate more events than there are transaction IDs, got {0}")]
+    TooManyEvents(usize),
+    #[error("Invalid amount distribution: \"{0}\"")]
+    InvalidDistribution(String),
+}
+
+/// Distribution of the amounts of generated deposits and withdrawals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountDistribution {
+    /// Amounts uniformly distributed between `min` and `max`. Written as `uniform:MIN:MAX`.
+    Uniform { min: f64, max: f64 },
+    /// Mostly small amounts with the occasional large one, averaging `mean`. Written as
+    /// `exponential:MEAN`.
+    Exponential { mean: f64 },
+}
+
+impl AmountDistribution {
+    fn sample(&self, rng: &mut impl Rng) -> Amount {
+        let value = match *self {
+            Self::Uniform { min, max } => rng.gen_range(min..=max),
+            Self::Exponential { mean } => -mean * (1.0 - rng.gen::<f64>()).ln(),
+        };
+        let units = (value * 10f64.powi(AMOUNT_SCALE as i32)).round() as i64;
+        Decimal::new(units.max(1), AMOUNT_SCALE).into()
+    }
+}
+
+impl Default for AmountDistribution {
+    fn default() -> Self {
+        Self::Exponential { mean: 100.0 }
+    }
+}
+
+impl FromStr for AmountDistribution {
+    type Err = GeneratorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || GeneratorError::InvalidDistribution(s.to_owned());
+        let parse = |x: &str| {
+            x.parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite() && *x > 0.0)
+                .ok_or_else(invalid)
+        };
+        let parts: Vec<_> = s.split(':').collect();
+        match parts.as_slice() {
+            ["uniform", min, max] => {
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(Self::Uniform { min, max })
+            }
+            ["exponential", mean] => Ok(Self::Exponential { mean: parse(mean)? }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for AmountDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Uniform { min, max } => write!(f, "uniform:{}:{}", min, max),
+            Self::Exponential { mean } => write!(f, "exponential:{}", mean),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    /// Seed of the random number generator. The same configuration always generates the same
+    /// events.
+    pub seed: u64,
+    /// Number of events to generate.
+    pub events: usize,
+    /// Number of clients, with IDs counting up from 1.
+    pub clients: u16,
+    /// Chance that a deposit is later disputed.
+    pub dispute_rate: f64,
+    /// Chance that a dispute ends in a chargeback rather than a resolution.
+    pub chargeback_rate: f64,
+    /// Chance that a new transaction is a withdrawal rather than a deposit.
+    pub withdrawal_rate: f64,
+    pub amounts: AmountDistribution,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            events: 1000,
+            clients: 100,
+            dispute_rate: 0.05,
+            chargeback_rate: 0.3,
+            withdrawal_rate: 0.3,
+            amounts: AmountDistribution::default(),
+        }
+    }
+}
+
+/// The next step in the lifecycle of a deposit that will be disputed.
+#[derive(Debug, Clone, Copy)]
+enum Lifecycle {
+    Dispute { chargeback: bool },
+    Settle { chargeback: bool },
+}
+
+/// The expected state of a client, tracked independently of `Account` so that it can serve as a
+/// test oracle for the engine.
+#[derive(Debug, Default, Clone)]
+struct ClientModel {
+    available: Amount,
+    held: Amount,
+    locked: bool,
+    deposits: HashMap<TransactionId, Amount>,
+}
+
+/// Generates a stream of events with coherent lifecycles: disputes only reference earlier
+/// deposits of the same client and are always either resolved or charged back, unless the stream
+/// ends first. Some withdrawals exceed the available funds, and locked clients keep trying to
+/// deposit and withdraw, so the stream also exercises rejections.
+///
+/// While generating, it keeps track of the final state the engine should reach, available from
+/// `expected_state`.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+    generated: usize,
+    /// Unset once every transaction ID has been used.
+    next_transaction_id: Option<u32>,
+    pending: Vec<(ClientId, TransactionId, Lifecycle)>,
+    clients: BTreeMap<ClientId, ClientModel>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Result<Self, GeneratorError> {
+        for (name, value) in [
+            ("Dispute rate", config.dispute_rate),
+            ("Chargeback rate", config.chargeback_rate),
+            ("Withdrawal rate", config.withdrawal_rate),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(GeneratorError::InvalidRate { name, value });
+            }
+        }
+        if config.clients == 0 {
+            return Err(GeneratorError::NoClients);
+        }
+        // Every event may start a new transaction, and IDs start at 1.
+        if u64::try_from(config.events).map_or(true, |events| events > u64::from(u32::MAX)) {
+            return Err(GeneratorError::TooManyEvents(config.events));
+        }
+
+        Ok(Self {
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            config,
+            generated: 0,
+            next_transaction_id: Some(1),
+            pending: Vec::new(),
+            clients: BTreeMap::new(),
+        })
+    }
+
+    /// The final state of every client that appeared in the events generated so far.
+    pub fn expected_state(&self) -> BTreeMap<ClientId, AccountState> {
+        self.clients
+            .iter()
+            .map(|(client, model)| {
+                let state = AccountState {
+                    available: model.available,
+                    held: model.held,
+                    total: model.available + model.held,
+                    locked: model.locked,
+                };
+                (*client, state)
+            })
+            .collect()
+    }
+
+    fn transaction_id(&mut self) -> Option<TransactionId> {
+        let transaction_id = self.next_transaction_id?;
+        self.next_transaction_id = transaction_id.checked_add(1);
+        Some(transaction_id.into())
+    }
+
+    fn continue_lifecycle(&mut self) -> Event {
+        let index = self.rng.gen_range(0..self.pending.len());
+        let (client, transaction_id, step) = self.pending.swap_remove(index);
+        let model = self.clients.entry(client).or_default();
+        let amount = model.deposits[&transaction_id];
+
+        let data = match step {
+            Lifecycle::Dispute { chargeback } => {
+                model.available -= amount;
+                model.held += amount;
+                self.pending
+                    .push((client, transaction_id, Lifecycle::Settle { chargeback }));
+                EventData::Dispute { transaction_id }
+            }
+            Lifecycle::Settle { chargeback: false } => {
+                model.held -= amount;
+                model.available += amount;
+                EventData::Resolve { transaction_id }
+            }
+            Lifecycle::Settle { chargeback: true } => {
+                model.held -= amount;
+                model.locked = true;
+                EventData::Chargeback { transaction_id }
+            }
+        };
+
+        Event { client, data }
+    }
+
+    /// Starts a new transaction, unless every transaction ID has been used.
+    fn new_transaction(&mut self) -> Option<Event> {
+        let client = ClientId::from(self.rng.gen_range(1..=self.config.clients));
+        let transaction_id = self.transaction_id()?;
+        let amount = self.config.amounts.sample(&mut self.rng);
+        let withdrawal = self.rng.gen_bool(self.config.withdrawal_rate);
+        let model = self.clients.entry(client).or_default();
+
+        if withdrawal {
+            if !model.locked && model.available >= amount {
+                model.available -= amount;
+            }
+            return Some(Event {
+                client,
+                data: EventData::Withdrawal {
+                    transaction_id,
+                    amount,
+                },
+            });
+        }
+
+        if !model.locked {
+            model.available += amount;
+            model.deposits.insert(transaction_id, amount);
+            if self.rng.gen_bool(self.config.dispute_rate) {
+                let chargeback = self.rng.gen_bool(self.config.chargeback_rate);
+                self.pending
+                    .push((client, transaction_id, Lifecycle::Dispute { chargeback }));
+            }
+        }
+        Some(Event {
+            client,
+            data: EventData::Deposit {
+                transaction_id,
+                amount,
+            },
+        })
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated == self.config.events {
+            return None;
+        }
+        self.generated += 1;
+
+        if !self.pending.is_empty() && self.rng.gen_bool(LIFECYCLE_PROBABILITY) {
+            Some(self.continue_lifecycle())
+        } else {
+            self.new_transaction()
+        }
+    }
+}
+
+/// Writes events as CSV in the engine's input format.
+pub fn write_events(
+    writer: impl Write,
+    events: impl IntoIterator<Item = Event>,
+) -> Result<(), io::Error> {
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+    writer.write_record(HEADERS)?;
+    for Event { client, data } in events {
+        let mut record = vec![
+            data.kind().to_string(),
+            client.to_string(),
+            data.transaction_id().to_string(),
+        ];
+        if let EventData::Deposit { amount, .. }
+        | EventData::Withdrawal { amount, .. }
+        | EventData::WithdrawalRequest { amount, .. } = data
+        {
+            record.push(amount.to_string());
+        }
+        writer.write_record(record)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(config: GeneratorConfig) -> Vec<Event> {
+        Generator::new(config).unwrap().collect()
+    }
+
+    #[test]
+    fn same_seed_generates_same_events() {
+        let config = GeneratorConfig {
+            events: 200,
+            ..GeneratorConfig::default()
+        };
+        let other_seed = GeneratorConfig {
+            seed: 1,
+            ..config.clone()
+        };
+
+        assert_eq!(generate(config.clone()), generate(config.clone()));
+        assert_ne!(generate(config), generate(other_seed));
+    }
+
+    #[test]
+    fn disputes_follow_deposits() {
+        let events = generate(GeneratorConfig {
+            events: 500,
+            clients: 5,
+            dispute_rate: 0.5,
+            ..GeneratorConfig::default()
+        });
+
+        let mut deposits = HashMap::new();
+        let mut disputed = HashMap::new();
+        for event in events {
+            let transaction_id = event.data.transaction_id();
+            match event.data {
+                EventData::Deposit { .. } => {
+                    deposits.insert(transaction_id, event.client);
+                }
+                EventData::Dispute { .. } => {
+                    assert_eq!(deposits.get(&transaction_id), Some(&event.client));
+                    disputed.insert(transaction_id, true);
+                }
+                EventData::Resolve { .. } | EventData::Chargeback { .. } => {
+                    assert_eq!(disputed.insert(transaction_id, false), Some(true));
+                }
+                EventData::Withdrawal { .. }
+                | EventData::WithdrawalRequest { .. }
+                | EventData::WithdrawalSettle { .. }
+                | EventData::WithdrawalCancel { .. }
+                | EventData::Clear { .. } => {}
+            }
+        }
+        assert!(disputed.len() > 10);
+    }
+
+    #[test]
+    fn transaction_ids_never_overflow() {
+        let config = GeneratorConfig {
+            withdrawal_rate: 1.0,
+            ..GeneratorConfig::default()
+        };
+        if let Ok(events) = usize::try_from(u64::from(u32::MAX) + 1) {
+            assert!(matches!(
+                Generator::new(GeneratorConfig {
+                    events,
+                    ..config.clone()
+                }),
+                Err(GeneratorError::TooManyEvents(_))
+            ));
+        }
+
+        let mut generator = Generator::new(config).unwrap();
+        generator.next_transaction_id = Some(u32::MAX - 1);
+        let transaction_ids: Vec<_> = generator
+            .map(|event| u32::from(event.data.transaction_id()))
+            .collect();
+        assert_eq!(transaction_ids, [u32::MAX - 1, u32::MAX]);
+    }
+
+    #[test]
+    fn parses_amount_distributions() {
+        assert_eq!(
+            "uniform:0.5:10".parse::<AmountDistribution>().unwrap(),
+            AmountDistribution::Uniform {
+                min: 0.5,
+                max: 10.0
+            }
+        );
+        assert_eq!(
+            "exponential:20".parse::<AmountDistribution>().unwrap(),
+            AmountDistribution::Exponential { mean: 20.0 }
+        );
+        assert!("uniform:10:1".parse::<AmountDistribution>().is_err());
+        assert!("normal:1".parse::<AmountDistribution>().is_err());
+    }
+}
@@ -0,0 +1,552 @@
+use {
+    crate::{
+        dedup::EVENT_ID,
+        engine::Location,
+        event::{Event, EventData, EventError, EventKind},
+        Amount, ClientId,
+    },
+    csv::{Position, Reader, ReaderBuilder, StringRecord, Trim},
+    derive_more::Display,
+    std::{
+        collections::{HashMap, HashSet},
+        io::{self, Read, Write},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+/// Creates a CSV reader configured for the engine's input format.
+pub(crate) fn csv_reader<R: Read>(reader: R) -> Reader<R> {
+    reader_builder().trim(Trim::All).from_reader(reader)
+}
+
+/// Creates a CSV reader that leaves fields untrimmed, for reading into a reused `ByteRecord`.
+/// Trimming in the reader replaces every record with a newly allocated one, so fields are trimmed
+/// as they are parsed instead.
+pub(crate) fn untrimmed_csv_reader<R: Read>(reader: R) -> Reader<R> {
+    reader_builder().from_reader(reader)
+}
+
+fn reader_builder() -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder.has_headers(true).flexible(true);
+    builder
+}
+
+#[derive(Debug, Error)]
+pub enum HeaderError {
+    #[error("Missing required columns: {}", list(.0))]
+    MissingColumns(Vec<Field>),
+    #[error("More than one column for \"{0}\"")]
+    DuplicateColumn(Field),
+    #[error("Unknown column \"{0}\"")]
+    UnknownColumn(String),
+    #[error("Unknown field: \"{0}\"")]
+    UnknownField(String),
+    #[error("Invalid header alias \"{0}\", expected HEADER=FIELD")]
+    InvalidAlias(String),
+}
+
+fn list(fields: &[Field]) -> String {
+    let fields: Vec<_> = fields.iter().map(ToString::to_string).collect();
+    fields.join(", ")
+}
+
+/// A field of an event, which the input may name with any of several headers.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    #[display(fmt = "type")]
+    Type,
+    #[display(fmt = "client")]
+    Client,
+    #[display(fmt = "tx")]
+    TransactionId,
+    #[display(fmt = "amount")]
+    Amount,
+    #[display(fmt = "{}", EVENT_ID)]
+    EventId,
+    /// Seconds since the Unix epoch, which only matter for clearing deposits after a number of
+    /// seconds and for limits over a window in seconds. Only read when one of them is used.
+    #[display(fmt = "timestamp")]
+    Timestamp,
+}
+
+impl Field {
+    pub const ALL: [Self; 6] = [
+        Self::Type,
+        Self::Client,
+        Self::TransactionId,
+        Self::Amount,
+        Self::EventId,
+        Self::Timestamp,
+    ];
+
+    fn is_required(self) -> bool {
+        !matches!(self, Self::EventId | Self::Timestamp)
+    }
+}
+
+impl FromStr for Field {
+    type Err = HeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|field| field.to_string() == s)
+            .copied()
+            .ok_or_else(|| HeaderError::UnknownField(s.to_owned()))
+    }
+}
+
+/// Another header for a field, written as `HEADER=FIELD`, such as `client_id=client`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderAlias {
+    pub header: String,
+    pub field: Field,
+}
+
+impl FromStr for HeaderAlias {
+    type Err = HeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((header, field)) if !header.trim().is_empty() => Ok(Self {
+                header: header.trim().to_owned(),
+                field: field.trim().parse()?,
+            }),
+            _ => Err(HeaderError::InvalidAlias(s.to_owned())),
+        }
+    }
+}
+
+/// How closely the input must follow its schema.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Ignores columns that don't name any field, fields past the last column, amounts of events
+    /// that don't have one, and whitespace around fields.
+    #[default]
+    Lenient,
+    /// Rejects unknown columns, and every row that doesn't have exactly the fields its type
+    /// requires, with the type written exactly as in the input format.
+    Strict,
+}
+
+/// Which column of the input holds each field of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Columns {
+    pub kind: usize,
+    pub client: usize,
+    pub transaction_id: usize,
+    pub amount: usize,
+    pub event_id: Option<usize>,
+    pub timestamp: Option<usize>,
+    /// Number of columns in the headers.
+    pub len: usize,
+    pub strictness: Strictness,
+}
+
+impl Default for Columns {
+    /// The columns in the order of the input format: `type, client, tx, amount`.
+    fn default() -> Self {
+        Self {
+            kind: 0,
+            client: 1,
+            transaction_id: 2,
+            amount: 3,
+            event_id: None,
+            timestamp: None,
+            len: 4,
+            strictness: Strictness::Lenient,
+        }
+    }
+}
+
+/// Resolves the columns of an input from its headers, so that columns can come in any order and
+/// under other names.
+#[derive(Debug, Clone)]
+pub struct HeaderMapping {
+    headers: HashMap<String, Field>,
+    strictness: Strictness,
+}
+
+impl Default for HeaderMapping {
+    fn default() -> Self {
+        Self {
+            headers: Field::ALL
+                .iter()
+                .map(|field| (field.to_string(), *field))
+                .collect(),
+            strictness: Strictness::default(),
+        }
+    }
+}
+
+impl HeaderMapping {
+    /// Also accepts `alias.header` as the header of `alias.field`.
+    pub fn with_alias(mut self, alias: HeaderAlias) -> Self {
+        self.headers.insert(alias.header, alias.field);
+        self
+    }
+
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Finds the column of every field, reporting all the required ones that are missing at once.
+    /// A `timestamp` column is treated like any column that doesn't name a field.
+    ///
+    /// An input without even a header row has no events to read, so it isn't an error.
+    pub fn resolve(&self, headers: &StringRecord) -> Result<Columns, HeaderError> {
+        self.resolve_with_timestamps(headers, false)
+    }
+
+    /// Like `resolve`, but also finds the `timestamp` column if `timestamps` is set.
+    pub fn resolve_with_timestamps(
+        &self,
+        headers: &StringRecord,
+        timestamps: bool,
+    ) -> Result<Columns, HeaderError> {
+        if headers.is_empty() {
+            return Ok(Columns::default());
+        }
+
+        let mut columns = HashMap::new();
+        for (index, header) in headers.iter().enumerate() {
+            let header = header.trim();
+            let field = match self.headers.get(header) {
+                Some(Field::Timestamp) if !timestamps => None,
+                field => field,
+            };
+            let field = match field {
+                Some(field) => *field,
+                None if self.strictness == Strictness::Strict => {
+                    return Err(HeaderError::UnknownColumn(header.to_owned()))
+                }
+                None => continue,
+            };
+            if columns.insert(field, index).is_some() {
+                return Err(HeaderError::DuplicateColumn(field));
+            }
+        }
+
+        let missing: Vec<_> = Field::ALL
+            .iter()
+            .filter(|field| field.is_required() && !columns.contains_key(field))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            return Err(HeaderError::MissingColumns(missing));
+        }
+
+        Ok(Columns {
+            kind: columns[&Field::Type],
+            client: columns[&Field::Client],
+            transaction_id: columns[&Field::TransactionId],
+            amount: columns[&Field::Amount],
+            event_id: columns.get(&Field::EventId).copied(),
+            timestamp: columns.get(&Field::Timestamp).copied(),
+            len: headers.len(),
+            strictness: self.strictness,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RowError {
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+    #[error("Header error: {0}")]
+    HeaderError(#[from] HeaderError),
+    #[error("Event error: {0}")]
+    EventError(#[from] EventError),
+}
+
+/// A row of input that couldn't be parsed into an event.
+#[derive(Debug)]
+pub struct InvalidRow {
+    pub location: Location,
+    pub error: RowError,
+}
+
+/// Parses every row of the input without applying any of them, collecting every malformed row
+/// rather than stopping at the first.
+///
+/// Only fails if the input can't be read at all. If the headers are invalid, that is the only row
+/// reported, since no other row can be parsed without them.
+pub fn validate(
+    source: Option<&str>,
+    reader: impl Read,
+    mapping: &HeaderMapping,
+) -> Result<Vec<InvalidRow>, io::Error> {
+    let mut invalid = Vec::new();
+    let location = |line| Location {
+        source: source.map(str::to_owned),
+        line,
+    };
+
+    let mut reader = untrimmed_csv_reader(reader);
+    let headers = match reader.headers() {
+        Ok(headers) => headers,
+        Err(e) if e.is_io_error() => return Err(e.into()),
+        Err(e) => {
+            invalid.push(InvalidRow {
+                location: location(e.position().map(Position::line)),
+                error: e.into(),
+            });
+            return Ok(invalid);
+        }
+    };
+    let columns = match mapping.resolve(headers) {
+        Ok(columns) => columns,
+        Err(e) => {
+            invalid.push(InvalidRow {
+                location: location(headers.position().map(Position::line)),
+                error: e.into(),
+            });
+            return Ok(invalid);
+        }
+    };
+    for record in reader.byte_records() {
+        match record {
+            Ok(record) => {
+                let line = record.position().map(Position::line);
+                if let Err(e) = Event::from_record(&record, &columns) {
+                    invalid.push(InvalidRow {
+                        location: location(line),
+                        error: e.into(),
+                    });
+                }
+            }
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => invalid.push(InvalidRow {
+                location: location(e.position().map(Position::line)),
+                error: e.into(),
+            }),
+        }
+    }
+
+    Ok(invalid)
+}
+
+/// Overview of an input, gathered without applying any of its events.
+#[derive(Debug, Default, Clone)]
+pub struct InputStats {
+    pub rows: u64,
+    pub invalid_rows: u64,
+    pub events: HashMap<EventKind, u64>,
+    pub clients: HashSet<ClientId>,
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+}
+
+impl InputStats {
+    /// Adds statistics about every valid row of an input. Fails if the input can't be read or its
+    /// headers are invalid.
+    pub fn read(&mut self, reader: impl Read, mapping: &HeaderMapping) -> Result<(), RowError> {
+        let mut reader = untrimmed_csv_reader(reader);
+        let columns = mapping.resolve(reader.headers()?)?;
+        for record in reader.byte_records() {
+            self.rows += 1;
+            let event = match record {
+                Ok(record) => Event::from_record(&record, &columns).map_err(RowError::from),
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                Err(e) => Err(e.into()),
+            };
+            match event {
+                Ok(event) => self.add(event),
+                Err(_) => self.invalid_rows += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, event: Event) {
+        *self.events.entry(event.data.kind()).or_default() += 1;
+        self.clients.insert(event.client);
+        match event.data {
+            EventData::Deposit { amount, .. } => {
+                self.deposited = self.deposited.saturating_add(amount)
+            }
+            EventData::Withdrawal { amount, .. } => {
+                self.withdrawn = self.withdrawn.saturating_add(amount)
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes the statistics as a single JSON object. Amounts are written as strings so that no
+    /// precision is lost to floating point conversion.
+    pub fn write_json(&self, mut writer: impl Write) -> Result<(), io::Error> {
+        write!(
+            writer,
+            r#"{{"rows":{},"invalid_rows":{},"clients":{},"events":{{"#,
+            self.rows,
+            self.invalid_rows,
+            self.clients.len()
+        )?;
+        for (i, kind) in EventKind::ALL.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            let count = self.events.get(kind).copied().unwrap_or_default();
+            write!(writer, r#""{}":{}"#, kind, count)?;
+        }
+        writeln!(
+            writer,
+            r#"}},"deposited":"{}","withdrawn":"{}"}}"#,
+            self.deposited, self.withdrawn
+        )?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = "\
+        type,       client, tx, amount
+        deposit,    1,      1,  10.5
+        deposit,    2,      2,  ten
+        withdrawal, 1,      3,  2
+        refund,     1,      1
+        dispute,    3,      1 \
+    ";
+
+    #[test]
+    fn reports_every_invalid_row() {
+        let invalid =
+            validate(Some("a.csv"), EVENTS.as_bytes(), &HeaderMapping::default()).unwrap();
+
+        let lines: Vec<_> = invalid.iter().map(|row| row.location.line).collect();
+        assert_eq!(lines, vec![Some(3), Some(5)]);
+        assert!(matches!(
+            invalid[1].error,
+            RowError::EventError(EventError::UnknownType(_))
+        ));
+    }
+
+    #[test]
+    fn resolves_columns_by_header() {
+        let headers = |headers: &str| StringRecord::from(headers.split(',').collect::<Vec<_>>());
+        let mapping = HeaderMapping::default().with_alias("client_id=client".parse().unwrap());
+
+        assert_eq!(
+            mapping
+                .resolve(&headers("amount, event_id, note, tx, client_id, type"))
+                .unwrap(),
+            Columns {
+                kind: 5,
+                client: 4,
+                transaction_id: 3,
+                amount: 0,
+                event_id: Some(1),
+                timestamp: None,
+                len: 6,
+                strictness: Strictness::Lenient,
+            }
+        );
+        assert!(matches!(
+            mapping.resolve(&headers("type,client,tx")),
+            Err(HeaderError::MissingColumns(missing)) if missing == [Field::Amount]
+        ));
+        assert!(matches!(
+            mapping.resolve(&headers("type,client,client_id,tx,amount")),
+            Err(HeaderError::DuplicateColumn(Field::Client))
+        ));
+        assert_eq!(
+            mapping
+                .resolve(&headers("amount,type"))
+                .unwrap_err()
+                .to_string(),
+            "Missing required columns: client, tx"
+        );
+        assert!("client_id".parse::<HeaderAlias>().is_err());
+        assert!("client_id=customer".parse::<HeaderAlias>().is_err());
+    }
+
+    #[test]
+    fn reports_invalid_headers_once() {
+        let events = "kind,client,tx,amount\ndeposit,1,1,1\n";
+        let invalid = validate(None, events.as_bytes(), &HeaderMapping::default()).unwrap();
+
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].location.line, Some(1));
+        assert!(matches!(invalid[0].error, RowError::HeaderError(_)));
+    }
+
+    #[test]
+    fn strict_mode_enforces_the_shape_of_every_row() {
+        let events = "\
+type,client,tx,amount
+deposit,1,1,1.5
+dispute,1,1
+resolve,1,1,
+dispute,1,1,1.5
+deposit,1,2,1,extra
+Deposit,1,3,1
+ withdrawal,1,4,1
+withdrawal,1,5,
+deposit,,6,1
+deposit,1,7
+";
+        let strict = HeaderMapping::default().with_strictness(Strictness::Strict);
+        let invalid = validate(None, events.as_bytes(), &strict).unwrap();
+
+        let errors: Vec<_> = invalid
+            .iter()
+            .map(|row| (row.location.line.unwrap(), row.error.to_string()))
+            .collect();
+        let expected = [
+            (5, "Unexpected amount for a dispute"),
+            (6, "Expected at most 4 fields, found 5"),
+            (
+                7,
+                r#"Type must be written exactly as "deposit", got "Deposit""#,
+            ),
+            (
+                8,
+                r#"Type must be written exactly as "withdrawal", got " withdrawal""#,
+            ),
+            (9, r#"Empty required field "amount""#),
+            (10, r#"Empty required field "client""#),
+            (11, r#"Missing required field "amount""#),
+        ];
+        assert_eq!(
+            errors,
+            expected
+                .iter()
+                .map(|(line, error)| (*line, format!("Event error: {}", error)))
+                .collect::<Vec<_>>()
+        );
+
+        let lenient = validate(None, events.as_bytes(), &HeaderMapping::default()).unwrap();
+        let lines: Vec<_> = lenient.iter().map(|row| row.location.line).collect();
+        assert_eq!(lines, [Some(9), Some(10), Some(11)]);
+        assert!(matches!(
+            validate(None, "type,client,tx,amount,note\n".as_bytes(), &strict).unwrap()[0].error,
+            RowError::HeaderError(HeaderError::UnknownColumn(_))
+        ));
+    }
+
+    #[test]
+    fn gathers_stats_without_applying() {
+        let mut stats = InputStats::default();
+        stats
+            .read(EVENTS.as_bytes(), &HeaderMapping::default())
+            .unwrap();
+
+        let mut json = Vec::new();
+        stats.write_json(&mut json).unwrap();
+
+        let expected = concat!(
+            r#"{"rows":5,"invalid_rows":2,"clients":2,"#,
+            r#""events":{"deposit":1,"withdrawal":1,"dispute":1,"resolve":0,"chargeback":0,"#,
+            r#""withdrawal_request":0,"withdrawal_settle":0,"withdrawal_cancel":0,"clear":0},"#,
+            r#""deposited":"10.5","withdrawn":"2"}"#,
+            "\n",
+        );
+        assert_eq!(expected, std::str::from_utf8(&json).unwrap());
+    }
+}
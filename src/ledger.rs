@@ -0,0 +1,110 @@
+use {
+    crate::{event::EventKind, Amount, ClientId, TransactionId},
+    std::{
+        collections::HashMap,
+        io::{self, Write},
+    },
+};
+
+const HEADERS: [&str; 13] = [
+    "client",
+    "tx",
+    "type",
+    "amount",
+    "available_before",
+    "held_before",
+    "reserved_before",
+    "pending_before",
+    "available_after",
+    "held_after",
+    "reserved_after",
+    "pending_after",
+    "locked",
+];
+
+/// A single operation that was successfully applied to an account.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LedgerEntry {
+    pub transaction_id: TransactionId,
+    pub kind: EventKind,
+    /// The amount moved by the operation. For disputes, resolutions and chargebacks this is the
+    /// amount of the referenced deposit.
+    pub amount: Amount,
+    pub available_before: Amount,
+    pub held_before: Amount,
+    pub reserved_before: Amount,
+    pub pending_before: Amount,
+    pub available_after: Amount,
+    pub held_after: Amount,
+    pub reserved_after: Amount,
+    pub pending_after: Amount,
+    pub locked: bool,
+}
+
+/// Per-client history of every applied operation, in the order they were applied.
+#[derive(Debug, Default, Clone)]
+pub struct Ledger {
+    entries: HashMap<ClientId, Vec<LedgerEntry>>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, client: ClientId, entry: LedgerEntry) {
+        self.entries.entry(client).or_default().push(entry);
+    }
+
+    pub fn entries(&self, client: ClientId) -> &[LedgerEntry] {
+        self.entries.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    /// Forgets every entry of a client after the first `len`.
+    pub(crate) fn truncate(&mut self, client: ClientId, len: usize) {
+        if let Some(entries) = self.entries.get_mut(&client) {
+            entries.truncate(len);
+            if entries.is_empty() {
+                self.entries.remove(&client);
+            }
+        }
+    }
+
+    /// Writes the ledger as CSV, either for a single client or for every client. Clients are
+    /// written in order of their IDs, so that exports of the same events are identical.
+    pub fn write_csv(&self, writer: impl Write, client: Option<ClientId>) -> Result<(), io::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(HEADERS)?;
+
+        let clients = match client {
+            Some(client) => vec![client],
+            None => {
+                let mut clients: Vec<_> = self.entries.keys().copied().collect();
+                clients.sort_unstable();
+                clients
+            }
+        };
+
+        for client in clients {
+            for entry in self.entries(client) {
+                writer.write_record(&[
+                    client.to_string(),
+                    entry.transaction_id.to_string(),
+                    entry.kind.to_string(),
+                    entry.amount.to_string(),
+                    entry.available_before.to_string(),
+                    entry.held_before.to_string(),
+                    entry.reserved_before.to_string(),
+                    entry.pending_before.to_string(),
+                    entry.available_after.to_string(),
+                    entry.held_after.to_string(),
+                    entry.reserved_after.to_string(),
+                    entry.pending_after.to_string(),
+                    entry.locked.to_string(),
+                ])?;
+            }
+        }
+
+        writer.flush()
+    }
+}
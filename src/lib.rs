@@ -1,20 +1,68 @@
 pub mod account;
+pub mod audit;
+pub mod clearing;
+pub mod dedup;
+pub mod diff;
 pub mod engine;
 pub mod event;
+pub mod generate;
+pub mod input;
+pub mod ledger;
+pub mod limits;
+pub mod metrics;
+pub mod observer;
+pub mod output;
+pub mod risk;
+pub mod snapshot;
+pub mod store;
+pub mod summary;
+pub mod window;
 
 use {
-    self::engine::{Engine, EngineError},
-    derive_more::{Add, AddAssign, AsRef, Display, From, FromStr, Into, Sub, SubAssign},
+    self::{
+        clearing::ClearingPolicy,
+        engine::{Engine, EngineError},
+        input::HeaderMapping,
+        limits::LimitConfig,
+        output::{Column, OutputFormat},
+        risk::RiskRules,
+        store::StorageKind,
+    },
+    derive_more::{Add, AddAssign, AsRef, Display, From, FromStr, Into, Neg, Sub, SubAssign},
     rust_decimal::Decimal,
     std::io::{Read, Write},
 };
 
-#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq, Hash, FromStr, From, Into, AsRef)]
+#[derive(
+    Debug,
+    Display,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    FromStr,
+    From,
+    Into,
+    AsRef,
+)]
 pub struct ClientId(u16);
 
 #[derive(Debug, Display, Clone, Default, Copy, PartialEq, Eq, Hash, FromStr, From, Into, AsRef)]
 pub struct TransactionId(u32);
 
+/// A point in the stream of events, which delays and rolling windows are measured from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Moment {
+    /// Number of events handled before this point.
+    pub events: u64,
+    /// The latest timestamp seen in the input, in seconds, which never goes back.
+    pub time: u64,
+}
+
 #[derive(
     Debug,
     Display,
@@ -33,16 +81,128 @@ pub struct TransactionId(u32);
     Sub,
     AddAssign,
     SubAssign,
+    Neg,
 )]
 pub struct Amount(Decimal);
 
-pub fn run(mut reader: impl Read, mut writer: impl Write) -> Result<(), EngineError> {
-    let mut engine = Engine::new();
-    engine.read_events(&mut reader)?;
-    engine.write_accounts_state(&mut writer)?;
+impl Amount {
+    /// Adds two amounts, or returns `None` if the exact sum can't be represented. Unlike
+    /// `Decimal::checked_add`, this also fails when the sum would have to be rounded to fit.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let sum = self.0.checked_add(other.0)?;
+        exact(sum, self, other)
+    }
+
+    /// Subtracts two amounts, or returns `None` if the exact difference can't be represented.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let difference = self.0.checked_sub(other.0)?;
+        exact(difference, self, other)
+    }
+
+    /// Adds two amounts, rounding the sum if needed and clamping it to the representable range.
+    /// Only suitable for reporting, where an approximate total is better than none.
+    pub fn saturating_add(self, other: Self) -> Self {
+        match self.0.checked_add(other.0) {
+            Some(sum) => Self(sum),
+            None if other.0.is_sign_negative() => Self(Decimal::MIN),
+            None => Self(Decimal::MAX),
+        }
+    }
+}
+
+/// `Decimal` rounds a result to fewer decimal places when it doesn't fit at the scale of its
+/// operands, which would silently change balances. Adding zero returns the other operand as is,
+/// whatever its scale, so that is always exact.
+fn exact(result: Decimal, a: Amount, b: Amount) -> Option<Amount> {
+    let exact = a.0.is_zero() || b.0.is_zero() || result.scale() >= a.0.scale().max(b.0.scale());
+    exact.then_some(Amount(result))
+}
+
+/// Options controlling a single run of the engine.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub output_format: OutputFormat,
+    /// Columns of the final accounts state, in order.
+    pub columns: Vec<Column>,
+    /// Keep a per-client ledger of every applied operation.
+    pub ledger: bool,
+    /// Check invariants after every event and at the end of the run.
+    pub audit: bool,
+    /// Measure the latency of every event for exporting with `Engine::write_metrics`.
+    pub metrics: bool,
+    /// How the engine stores accounts.
+    pub storage: StorageKind,
+    /// How to find the columns of the input from its headers.
+    pub header_mapping: HeaderMapping,
+    /// When deposited funds become available.
+    pub clearing: ClearingPolicy,
+    /// Limits every account is held to.
+    pub limits: LimitConfig,
+    /// Rules that lock or freeze accounts automatically.
+    pub risk_rules: RiskRules,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            output_format: OutputFormat::default(),
+            columns: Column::DEFAULT.to_vec(),
+            ledger: false,
+            audit: false,
+            metrics: false,
+            storage: StorageKind::default(),
+            header_mapping: HeaderMapping::default(),
+            clearing: ClearingPolicy::default(),
+            limits: LimitConfig::default(),
+            risk_rules: RiskRules::default(),
+        }
+    }
+}
+
+pub fn run(reader: impl Read, writer: impl Write) -> Result<(), EngineError> {
+    run_with_options(reader, writer, &Options::default())?;
     Ok(())
 }
 
+impl Options {
+    /// Creates an engine that keeps the state required by these options.
+    pub fn engine(&self) -> Engine {
+        let mut engine = Engine::new()
+            .with_storage(self.storage)
+            .with_header_mapping(self.header_mapping.clone())
+            .with_clearing(self.clearing)
+            .with_limits(self.limits.clone())
+            .with_risk_rules(self.risk_rules.clone());
+        if self.ledger {
+            engine = engine.with_ledger();
+        }
+        if self.audit {
+            engine = engine.with_audit();
+        }
+        if self.metrics {
+            engine = engine.with_metrics();
+        }
+        engine
+    }
+}
+
+/// Processes every event from `reader`, writes the final accounts state to `writer` and returns
+/// the engine so that any other state it kept can be inspected.
+pub fn run_with_options(
+    mut reader: impl Read,
+    writer: impl Write,
+    options: &Options,
+) -> Result<Engine, EngineError> {
+    let mut engine = options.engine();
+    engine.read_events(&mut reader)?;
+    engine.audit()?;
+    let mut formatter = options
+        .output_format
+        .formatter_with_columns(&options.columns, writer);
+    engine.write_accounts(formatter.as_mut())?;
+    Ok(engine)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -131,4 +291,937 @@ mod tests {
 
         assert!(expected1 == actual || expected2 == actual);
     }
+
+    #[test]
+    fn ledger_export() {
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  10.5
+            deposit,    2,      2,  3
+            deposit,    10,     6,  1
+            deposit,    3,      7,  1
+            withdrawal, 1,      3,  0.5
+            withdrawal, 1,      4,  100
+            withdrawal_request, 1, 5, 2
+            withdrawal_settle,  1, 5,
+            dispute,    1,      1
+            chargeback, 1,      1 \
+        ";
+
+        let expected = "\
+            client,tx,type,amount,available_before,held_before,reserved_before,pending_before,\
+            available_after,held_after,reserved_after,pending_after,locked\n\
+            1,1,deposit,10.5,0,0,0,0,10.5,0,0,0,false\n\
+            1,3,withdrawal,0.5,10.5,0,0,0,10.0,0,0,0,false\n\
+            1,5,withdrawal_request,2,10.0,0,0,0,8.0,0,2,0,false\n\
+            1,5,withdrawal_settle,2,8.0,0,2,0,8.0,0,0,0,false\n\
+            1,1,dispute,10.5,8.0,0,0,0,-2.5,10.5,0,0,false\n\
+            1,1,chargeback,10.5,-2.5,10.5,0,0,-2.5,0.0,0,0,true\n\
+        ";
+
+        let options = crate::Options {
+            ledger: true,
+            ..Default::default()
+        };
+        let engine = crate::run_with_options(events.as_bytes(), std::io::sink(), &options).unwrap();
+        let mut actual = Vec::new();
+        engine
+            .ledger()
+            .unwrap()
+            .write_csv(&mut actual, Some(crate::ClientId::from(1)))
+            .unwrap();
+        let actual = std::str::from_utf8(&actual).unwrap();
+
+        assert_eq!(expected, actual);
+
+        let mut actual = Vec::new();
+        engine
+            .ledger()
+            .unwrap()
+            .write_csv(&mut actual, None)
+            .unwrap();
+        let clients: Vec<_> = std::str::from_utf8(&actual)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(clients, ["1", "1", "1", "1", "1", "1", "2", "3", "10"]);
+    }
+
+    #[test]
+    fn audit_passes_on_valid_run() {
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  101.291
+            deposit,    1,      2,  101.291
+            dispute,    1,      2
+            deposit,    2,      3,  55.55
+            dispute,    2,      3
+            withdrawal, 2,      4,  10
+            withdrawal, 2,      5,  100
+            chargeback, 1,      2
+            resolve,    2,      3
+            resolve,    2,      3
+            withdrawal, 1,      6,  1 \
+        ";
+
+        let options = crate::Options {
+            audit: true,
+            ..Default::default()
+        };
+
+        assert!(crate::run_with_options(events.as_bytes(), std::io::sink(), &options).is_ok());
+    }
+
+    #[test]
+    fn statistics_are_counted() {
+        use crate::event::EventKind;
+
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  10
+            withdrawal, 1,      2,  20
+            withdrawal, 1,      3,  20
+            dispute,    1,      1
+            chargeback, 1,      1
+            deposit,    1,      4,  5 \
+        ";
+
+        let engine =
+            crate::run_with_options(events.as_bytes(), std::io::sink(), &Default::default())
+                .unwrap();
+        let summary = engine.summary(std::time::Duration::from_secs(1));
+
+        assert_eq!(summary.statistics.rows_read, 6);
+        assert_eq!(summary.statistics.applied(EventKind::Deposit), 1);
+        assert_eq!(summary.statistics.applied(EventKind::Withdrawal), 0);
+        assert_eq!(summary.statistics.applied(EventKind::Chargeback), 1);
+        assert_eq!(
+            summary
+                .statistics
+                .rejected
+                .get("Withdraw::InsufficientFunds"),
+            Some(&2)
+        );
+        assert_eq!(
+            summary.statistics.rejected.get("Deposit::AccountLocked"),
+            Some(&1)
+        );
+        assert_eq!(summary.locked_accounts, 1);
+    }
+
+    #[test]
+    fn query_engine_state() {
+        use {crate::account::DepositState, rust_decimal_macros::dec};
+
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  10
+            deposit,    1,      2,  2.5
+            deposit,    2,      3,  7
+            dispute,    1,      2
+            dispute,    2,      3
+            chargeback, 2,      3 \
+        ";
+
+        let engine =
+            crate::run_with_options(events.as_bytes(), std::io::sink(), &Default::default())
+                .unwrap();
+        let client = crate::ClientId::from(1);
+        let tx = crate::TransactionId::from;
+
+        assert_eq!(engine.accounts().count(), 2);
+        assert!(engine.account(crate::ClientId::from(3)).is_none());
+        assert_eq!(
+            engine.account(client).unwrap().held_funds(),
+            crate::Amount::from(dec!(2.5))
+        );
+        assert_eq!(
+            engine.open_disputes(client),
+            vec![(tx(2), crate::Amount::from(dec!(2.5)))]
+        );
+        assert!(engine.open_disputes(crate::ClientId::from(2)).is_empty());
+        assert_eq!(
+            engine
+                .account(crate::ClientId::from(2))
+                .and_then(|account| account.processed_deposit(tx(3)))
+                .map(|deposit| deposit.state()),
+            Some(DepositState::Reversed)
+        );
+    }
+
+    #[test]
+    fn failed_batch_is_rolled_back() {
+        use {
+            crate::{
+                engine::Engine,
+                event::{Event, EventData},
+                Amount, ClientId, TransactionId,
+            },
+            rust_decimal_macros::dec,
+        };
+
+        let event = |client: u16, data| Event {
+            client: ClientId::from(client),
+            data,
+        };
+        let deposit = |client, tx: u32, amount| {
+            event(
+                client,
+                EventData::Deposit {
+                    transaction_id: TransactionId::from(tx),
+                    amount: Amount::from(amount),
+                },
+            )
+        };
+        let dispute = |client, tx: u32| {
+            event(
+                client,
+                EventData::Dispute {
+                    transaction_id: TransactionId::from(tx),
+                },
+            )
+        };
+        let chargeback = |client, tx: u32| {
+            event(
+                client,
+                EventData::Chargeback {
+                    transaction_id: TransactionId::from(tx),
+                },
+            )
+        };
+
+        let mut engine = Engine::new().with_ledger().with_audit();
+        engine
+            .apply_batch(vec![deposit(1, 1, dec!(10)), deposit(1, 2, dec!(5))])
+            .unwrap();
+
+        let error = engine
+            .apply_batch(vec![
+                dispute(1, 2),
+                chargeback(1, 2),
+                deposit(2, 3, dec!(1)),
+                deposit(1, 1, dec!(1)),
+            ])
+            .unwrap_err();
+
+        assert_eq!(error.index, 3);
+        assert_eq!(error.event, deposit(1, 1, dec!(1)));
+        assert!(engine.account(ClientId::from(2)).is_none());
+        let account = engine.account(ClientId::from(1)).unwrap();
+        assert!(!account.is_locked());
+        assert_eq!(account.available_funds(), Amount::from(dec!(15)));
+        assert_eq!(account.held_funds(), Amount::from(dec!(0)));
+        assert!(engine.open_disputes(ClientId::from(1)).is_empty());
+        assert_eq!(engine.ledger().unwrap().entries(ClientId::from(1)).len(), 2);
+        assert!(engine.audit().is_ok());
+
+        engine
+            .apply_batch(vec![dispute(1, 2), chargeback(1, 2)])
+            .unwrap();
+        assert!(engine.account(ClientId::from(1)).unwrap().is_locked());
+    }
+
+    #[test]
+    fn rolled_back_batch_keeps_withdrawals_in_window() {
+        use {
+            crate::{
+                account::{AccountError, WithdrawError},
+                engine::Engine,
+                event::{Event, EventData},
+                limits::{LimitConfig, Limits},
+                window::Window,
+                Amount, ClientId, TransactionId,
+            },
+            rust_decimal_macros::dec,
+        };
+
+        let deposit = |tx: u32, amount| Event {
+            client: ClientId::from(1),
+            data: EventData::Deposit {
+                transaction_id: TransactionId::from(tx),
+                amount: Amount::from(amount),
+            },
+        };
+        let withdrawal = |tx: u32, amount| Event {
+            client: ClientId::from(1),
+            data: EventData::Withdrawal {
+                transaction_id: TransactionId::from(tx),
+                amount: Amount::from(amount),
+            },
+        };
+
+        let mut engine = Engine::new().with_limits(LimitConfig::new(Limits {
+            max_withdrawn: Some((Amount::from(dec!(100)), Window::Events(3))),
+            ..Default::default()
+        }));
+        engine.handle_event(deposit(1, dec!(500))).unwrap();
+        engine.handle_event(withdrawal(2, dec!(60))).unwrap();
+
+        // The batch moves the first withdrawal out of the window before failing.
+        engine
+            .apply_batch(vec![
+                deposit(3, dec!(1)),
+                deposit(4, dec!(1)),
+                deposit(5, dec!(1)),
+                deposit(1, dec!(1)),
+            ])
+            .unwrap_err();
+
+        let error = engine.handle_event(withdrawal(6, dec!(60))).unwrap_err();
+        assert!(matches!(
+            error,
+            AccountError::Withdraw(WithdrawError::ExceedsMaxWithdrawn(..))
+        ));
+        assert_eq!(
+            engine.account(ClientId::from(1)).unwrap().available_funds(),
+            Amount::from(dec!(440))
+        );
+    }
+
+    #[test]
+    fn replayed_file_is_skipped() {
+        use crate::dedup::Deduplicator;
+
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  10
+            dispute,    1,      1
+            resolve,    1,      1
+            withdrawal, 1,      2,  4 \
+        ";
+
+        let mut engine = crate::engine::Engine::new().with_deduplicator(Deduplicator::new());
+        engine
+            .read_source(Some("a.csv"), events.as_bytes())
+            .unwrap();
+        engine
+            .read_source(Some("a.csv"), events.as_bytes())
+            .unwrap();
+        engine
+            .read_source(Some("b.csv"), events.as_bytes())
+            .unwrap();
+
+        let expected = "\
+            client,available,held,total,locked\n\
+            1,2,0,2,false\n\
+        ";
+        let mut actual = Vec::new();
+        engine.write_accounts_state(&mut actual).unwrap();
+
+        assert_eq!(expected, std::str::from_utf8(&actual).unwrap());
+        assert_eq!(engine.statistics().duplicates, 4);
+        assert_eq!(engine.statistics().total_rejected(), 1);
+    }
+
+    #[test]
+    fn rewritten_rows_are_not_skipped() {
+        use {crate::dedup::Deduplicator, rust_decimal_macros::dec};
+
+        let mut engine = crate::engine::Engine::new().with_deduplicator(Deduplicator::new());
+        engine
+            .read_source(
+                Some("in.csv"),
+                "type,client,tx,amount\ndeposit,1,1,10".as_bytes(),
+            )
+            .unwrap();
+        engine
+            .read_source(
+                Some("in.csv"),
+                "type,client,tx,amount\ndeposit,2,2,99".as_bytes(),
+            )
+            .unwrap();
+        engine
+            .read_source(
+                Some("in.csv"),
+                "type, client, tx, amount\ndeposit, 2, 2, 99".as_bytes(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.account(1.into()).unwrap().total_funds(),
+            crate::Amount::from(dec!(10))
+        );
+        assert_eq!(
+            engine.account(2.into()).unwrap().total_funds(),
+            crate::Amount::from(dec!(99))
+        );
+        assert_eq!(engine.statistics().duplicates, 1);
+    }
+
+    #[test]
+    fn events_with_same_id_are_skipped() {
+        use crate::dedup::Deduplicator;
+
+        let events = "\
+            type,       client, tx, amount, event_id
+            deposit,    1,      1,  10,     a
+            dispute,    1,      1,  ,       b
+            dispute,    1,      1,  ,       b
+            resolve,    1,      1,  ,       c
+            resolve,    1,      1,  ,       c
+            dispute,    1,      1,  ,       d \
+        ";
+
+        let mut engine = crate::engine::Engine::new().with_deduplicator(Deduplicator::new());
+        engine.read_events(events.as_bytes()).unwrap();
+
+        let expected = "\
+            client,available,held,total,locked\n\
+            1,0,10,10,false\n\
+        ";
+        let mut actual = Vec::new();
+        engine.write_accounts_state(&mut actual).unwrap();
+
+        assert_eq!(expected, std::str::from_utf8(&actual).unwrap());
+        assert_eq!(engine.statistics().duplicates, 2);
+        assert_eq!(engine.statistics().total_rejected(), 0);
+    }
+
+    #[test]
+    fn accounts_are_resumed_from_snapshot() {
+        use crate::{
+            clearing::ClearingPolicy, dedup::Deduplicator, limits::LimitConfig, risk::RiskRules,
+            store::StorageKind,
+        };
+
+        let first = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  100
+            deposit,    2,      2,  50
+            deposit,    3,      4,  20
+            deposit,    2,      5,  10
+            withdrawal, 1,      3,  30
+            dispute,    2,      2,
+            deposit,    3,      8,  20 \
+        ";
+        let second = "\
+            type,       client, tx, amount
+            resolve,    2,      2,
+            dispute,    2,      5,
+            withdrawal, 1,      6,  25
+            withdrawal, 3,      7,  40 \
+        ";
+
+        let options = crate::Options {
+            storage: StorageKind::Dense,
+            audit: true,
+            clearing: ClearingPolicy::AfterEvents(3),
+            limits: LimitConfig::load("client, max_withdrawn, window\n*, 50, events:10".as_bytes())
+                .unwrap(),
+            risk_rules: RiskRules::load("rule, action\ndisputes:2:10, lock".as_bytes()).unwrap(),
+            ..Default::default()
+        };
+
+        let mut engine = options.engine().with_deduplicator(Deduplicator::new());
+        engine.read_source(Some("a.csv"), first.as_bytes()).unwrap();
+        let mut snapshot = Vec::new();
+        engine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut engine = options.engine().with_deduplicator(Deduplicator::new());
+        engine.load_snapshot(snapshot.as_slice()).unwrap();
+        assert!(engine.load_snapshot(snapshot.as_slice()).is_err());
+        engine.read_source(Some("a.csv"), first.as_bytes()).unwrap();
+        engine
+            .read_source(Some("b.csv"), second.as_bytes())
+            .unwrap();
+        engine.audit().unwrap();
+
+        let expected = "\
+            client,available,held,total,locked\n\
+            1,70,0,70,false\n\
+            2,50,10,60,true\n\
+            3,0,0,0,false\n\
+        ";
+        let mut actual = Vec::new();
+        engine.write_accounts_state(&mut actual).unwrap();
+
+        assert_eq!(expected, std::str::from_utf8(&actual).unwrap());
+        assert_eq!(engine.statistics().duplicates, 7);
+        assert_eq!(
+            engine.statistics().rejected,
+            std::collections::BTreeMap::from([("Withdraw::ExceedsMaxWithdrawn", 1)])
+        );
+    }
+
+    #[test]
+    fn errors_report_source_and_line() {
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  10
+            deposit,    1,      2,  ten \
+        ";
+
+        let mut engine = crate::engine::Engine::new();
+        let error = engine
+            .read_source(Some("a.csv"), events.as_bytes())
+            .unwrap_err();
+
+        assert!(error.to_string().starts_with("a.csv, line 3: "));
+    }
+
+    #[test]
+    fn columns_are_found_by_header() {
+        use crate::input::{HeaderMapping, Strictness};
+
+        let events = "\
+            client_id, type,       transaction, timestamp,  amount
+            1,         deposit,    1,           1700000000, 10
+            1,         withdrawal, 2,           1700000001, 2.5
+            2,         deposit,    3,           1700000002, 1 \
+        ";
+
+        let mapping = HeaderMapping::default()
+            .with_alias("client_id=client".parse().unwrap())
+            .with_alias("transaction=tx".parse().unwrap());
+        let options = crate::Options {
+            header_mapping: mapping.clone(),
+            ..Default::default()
+        };
+        let engine = crate::run_with_options(events.as_bytes(), std::io::sink(), &options).unwrap();
+        let available = |client: u16| engine.account(client.into()).unwrap().available_funds();
+        assert_eq!(available(1).to_string(), "7.5");
+        assert_eq!(available(2).to_string(), "1");
+
+        let strict = crate::Options {
+            header_mapping: mapping.with_strictness(Strictness::Strict),
+            ..Default::default()
+        };
+        let error = crate::run_with_options(events.as_bytes(), std::io::sink(), &strict)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, r#"line 1: Header error: Unknown column "timestamp""#);
+    }
+
+    #[test]
+    fn custom_event_types_have_handlers() {
+        use crate::{
+            engine::{Engine, EngineError},
+            event::{CustomEvent, EventData, EventKind, EventType, RegistryError},
+        };
+
+        let events = "\
+            type,       client, tx, amount
+            Deposit,    1,      1,  100
+            WITHDRAW,   1,      2,  10
+            fee,        1,      3,  1.5
+            Fee,        1,      4
+            dispute,    1,      1
+            cb,         1,      1 \
+        ";
+
+        let mut engine = Engine::new();
+        engine
+            .register_event_type("fee", |engine, fee| {
+                let data = EventData::Withdrawal {
+                    transaction_id: fee.transaction_id,
+                    amount: fee.amount.unwrap_or_else(|| "0.5".parse().unwrap()),
+                };
+                engine.handle_event(crate::event::Event {
+                    client: fee.client,
+                    data,
+                })
+            })
+            .unwrap();
+        engine.read_events(events.as_bytes()).unwrap();
+
+        let account = engine.account(1.into()).unwrap();
+        assert_eq!(account.total_funds().to_string(), "-12.0");
+        assert!(account.is_locked());
+        assert_eq!(engine.statistics().applied(EventKind::Withdrawal), 3);
+        assert!(engine
+            .add_type_alias("withdraw", EventType::Builtin(EventKind::Deposit))
+            .is_err());
+
+        let fee = match engine.types().find(b"fee") {
+            Some((_, EventType::Custom(kind))) => kind,
+            _ => panic!("fee isn't a custom type"),
+        };
+        let mut other = Engine::new();
+        other
+            .register_event_type("refund", |_, _| panic!("handled a fee as a refund"))
+            .unwrap();
+        for mut engine in [Engine::new(), other] {
+            let error = engine
+                .handle_custom_event(CustomEvent {
+                    kind: fee,
+                    client: 1.into(),
+                    transaction_id: 5.into(),
+                    amount: None,
+                })
+                .unwrap_err();
+            assert!(matches!(
+                error,
+                EngineError::RegistryError(RegistryError::UnknownCustomKind(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn withdrawals_can_be_requested_and_settled_later() {
+        use crate::output::Column;
+
+        let events = "\
+            type,               client, tx, amount
+            deposit,            1,      1,  100
+            withdrawal_request, 1,      2,  30
+            withdrawal_request, 1,      3,  60
+            withdrawal,         1,      4,  20
+            withdrawal_settle,  1,      2
+            withdrawal_cancel,  1,      3
+            withdrawal_cancel,  1,      2
+            withdrawal_request, 2,      5,  1 \
+        ";
+
+        let options = crate::Options {
+            columns: vec![
+                Column::Client,
+                Column::Available,
+                Column::Reserved,
+                Column::Total,
+            ],
+            audit: true,
+            storage: crate::store::StorageKind::Dense,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let engine = crate::run_with_options(events.as_bytes(), &mut output, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,reserved,total\n1,70,0,70\n2,0,0,0\n"
+        );
+        assert_eq!(
+            engine.statistics().rejected,
+            std::collections::BTreeMap::from([
+                ("Withdraw::InsufficientFunds", 2),
+                ("WithdrawalState::WithdrawalAlreadySettled", 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn deposits_clear_according_to_policy() {
+        use crate::{clearing::ClearingPolicy, output::Column};
+
+        let events = "\
+            type,       client, tx, amount, timestamp
+            deposit,    1,      1,  100,    1000
+            withdrawal, 1,      2,  10,     1010
+            deposit,    1,      3,  50,     1050
+            clear,      1,      3,  ,       1060
+            withdrawal, 1,      4,  20,     1100 \
+        ";
+
+        let state = |clearing| {
+            let options = crate::Options {
+                columns: vec![Column::Available, Column::Pending, Column::Total],
+                audit: true,
+                clearing,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            crate::run_with_options(events.as_bytes(), &mut output, &options).unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .nth(1)
+                .unwrap()
+                .to_owned()
+        };
+
+        assert_eq!(state(ClearingPolicy::Immediate), "120,0,120");
+        assert_eq!(state(ClearingPolicy::AfterEvents(2)), "130,0,130");
+        assert_eq!(state(ClearingPolicy::Explicit), "30,100,130");
+        assert_eq!(state(ClearingPolicy::AfterSeconds(100)), "130,0,130");
+        assert_eq!(state(ClearingPolicy::AfterSeconds(101)), "30,100,130");
+    }
+
+    #[test]
+    fn timestamps_are_only_read_when_used() {
+        use crate::clearing::ClearingPolicy;
+
+        let events = "\
+            type,    client, tx, amount, timestamp
+            deposit, 1,      1,  10,     2024-01-01T00:00:00Z \
+        ";
+
+        let mut output = Vec::new();
+        crate::run_with_options(events.as_bytes(), &mut output, &Default::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,10,0,10,false\n"
+        );
+
+        let options = crate::Options {
+            clearing: ClearingPolicy::AfterSeconds(60),
+            ..Default::default()
+        };
+        let error = crate::run_with_options(events.as_bytes(), std::io::sink(), &options)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("line 2: Event error: Error parsing timestamp"));
+    }
+
+    #[test]
+    fn accounts_are_held_to_their_limits() {
+        use crate::{limits::LimitConfig, store::StorageKind};
+
+        let limits = "\
+            client, max_withdrawn, window,      max_balance
+            *,      100,           seconds:60,  
+            2,      ,              ,            500
+        ";
+        let events = "\
+            type,       client, tx, amount, timestamp
+            deposit,    1,      1,  500,    0
+            withdrawal, 1,      2,  60,     10
+            withdrawal, 1,      3,  60,     20
+            withdrawal, 1,      4,  60,     70
+            deposit,    2,      5,  400,    80
+            deposit,    2,      6,  200,    90
+            withdrawal, 2,      7,  150,    100 \
+        ";
+
+        let options = crate::Options {
+            storage: StorageKind::Dense,
+            audit: true,
+            limits: LimitConfig::load(limits.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let engine = crate::run_with_options(events.as_bytes(), &mut output, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,380,0,380,false\n2,400,0,400,false\n"
+        );
+        assert_eq!(
+            engine.statistics().rejected,
+            std::collections::BTreeMap::from([
+                ("Deposit::ExceedsMaxBalance", 1),
+                ("Withdraw::ExceedsMaxWithdrawn", 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn risk_rules_lock_accounts() {
+        use crate::{account::LockReason, output::Column, risk::RiskRules, store::StorageKind};
+
+        let rules = "\
+            rule,             action
+            negative_balance, freeze
+            disputes:2:10,    lock
+        ";
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  100
+            withdrawal, 1,      2,  80
+            dispute,    1,      1,
+            withdrawal, 1,      7,  10
+            deposit,    1,      8,  10
+            deposit,    2,      3,  10
+            deposit,    2,      4,  10
+            dispute,    2,      3,
+            resolve,    2,      3,
+            dispute,    2,      4,
+            deposit,    3,      5,  10
+            dispute,    3,      5,
+            chargeback, 3,      5,
+            deposit,    4,      6,  10
+            dispute,    4,      6, \
+        ";
+
+        let options = crate::Options {
+            columns: vec![
+                Column::Client,
+                Column::Locked,
+                Column::Frozen,
+                Column::LockReason,
+            ],
+            storage: StorageKind::Dense,
+            audit: true,
+            risk_rules: RiskRules::load(rules.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let engine = crate::run_with_options(events.as_bytes(), &mut output, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+            client,locked,frozen,lock_reason\n\
+            1,false,true,negative_balance\n\
+            2,true,false,disputes\n\
+            3,true,false,chargeback\n\
+            4,false,false,\n\
+            "
+        );
+        assert_eq!(
+            engine.account(1.into()).unwrap().lock_reason(),
+            Some(LockReason::NegativeBalance)
+        );
+        assert_eq!(
+            engine.statistics().rejected,
+            std::collections::BTreeMap::from([("Withdraw::AccountFrozen", 1)])
+        );
+    }
+
+    #[test]
+    fn locks_record_their_provenance() {
+        use crate::{
+            account::{Lock, LockCause, LockReason},
+            output::Column,
+            risk::{RiskAction, RiskRule, RiskRules},
+            store::StorageKind,
+        };
+
+        let events = "\
+            type,       client, tx, amount
+            deposit,    1,      1,  100
+            deposit,    2,      2,  100
+            withdrawal, 1,      3,  80
+            dispute,    1,      1,
+            dispute,    2,      2,
+            chargeback, 2,      2,
+            deposit,    3,      4,  10 \
+        ";
+
+        let options = crate::Options {
+            columns: vec![
+                Column::Client,
+                Column::LockCause,
+                Column::LockTransaction,
+                Column::LockSequence,
+            ],
+            storage: StorageKind::Dense,
+            risk_rules: RiskRules::default().with_rule(RiskRule::NegativeBalance, RiskAction::Lock),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let mut engine = crate::run_with_options(events.as_bytes(), &mut output, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+            client,lock_cause,lock_tx,lock_sequence\n\
+            1,rule,1,4\n\
+            2,chargeback,2,6\n\
+            3,,,\n\
+            "
+        );
+
+        assert!(engine.lock_account(3.into()));
+        assert!(!engine.lock_account(3.into()));
+        assert!(!engine.lock_account(4.into()));
+        let lock = engine.account(3.into()).unwrap().lock().copied();
+        assert_eq!(lock, Some(Lock::new(LockReason::Admin).with_sequence(7)));
+        assert_eq!(lock.unwrap().cause(), LockCause::Admin);
+
+        let mut snapshot = Vec::new();
+        engine.save_snapshot(&mut snapshot).unwrap();
+        let mut resumed = options.engine();
+        resumed.load_snapshot(snapshot.as_slice()).unwrap();
+        for client in 1..=3 {
+            assert_eq!(
+                resumed.account(client.into()).unwrap().lock(),
+                engine.account(client.into()).unwrap().lock()
+            );
+        }
+    }
+
+    #[test]
+    fn ledger_shows_pending_funds() {
+        use crate::clearing::ClearingPolicy;
+
+        let events = "\
+            type,    client, tx, amount
+            deposit, 1,      1,  10
+            clear,   1,      1, \
+        ";
+
+        let options = crate::Options {
+            ledger: true,
+            clearing: ClearingPolicy::Explicit,
+            ..Default::default()
+        };
+        let engine = crate::run_with_options(events.as_bytes(), std::io::sink(), &options).unwrap();
+        let mut actual = Vec::new();
+        engine
+            .ledger()
+            .unwrap()
+            .write_csv(&mut actual, None)
+            .unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&actual).unwrap(),
+            "\
+            client,tx,type,amount,available_before,held_before,reserved_before,pending_before,\
+            available_after,held_after,reserved_after,pending_after,locked\n\
+            1,1,deposit,10,0,0,0,0,0,0,0,10,false\n\
+            1,1,clear,10,0,0,0,10,10,0,0,0,false\n\
+            "
+        );
+    }
+
+    #[test]
+    fn amounts_are_never_rounded() {
+        use {crate::Amount, rust_decimal::Decimal};
+
+        let max = Amount::from(Decimal::MAX);
+        let small = Amount::from(Decimal::new(1, 4));
+        let large: Amount = "5000000000000000000000000.0000".parse().unwrap();
+
+        assert!(max.checked_add(Amount::from(Decimal::ONE)).is_none());
+        assert!(max.checked_add(small).is_none());
+        assert!(large.checked_add(large).is_none());
+        assert!(large.checked_sub(small).is_some());
+        assert_eq!(max.saturating_add(max), max);
+    }
+
+    #[test]
+    fn generated_events_reach_expected_state() {
+        use crate::{
+            diff,
+            generate::{Generator, GeneratorConfig},
+            store::StorageKind,
+        };
+
+        let mut generator = Generator::new(GeneratorConfig {
+            events: 5000,
+            clients: 20,
+            dispute_rate: 0.2,
+            chargeback_rate: 0.5,
+            ..GeneratorConfig::default()
+        })
+        .unwrap();
+        let mut input = Vec::new();
+        crate::generate::write_events(&mut input, generator.by_ref()).unwrap();
+
+        for storage in [StorageKind::Map, StorageKind::Dense] {
+            let options = crate::Options {
+                storage,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            crate::run_with_options(input.as_slice(), &mut output, &options).unwrap();
+
+            let actual = diff::read_accounts_state(output.as_slice()).unwrap();
+            let differences = diff::diff(&generator.expected_state(), &actual);
+            assert!(differences.is_empty(), "{}: {:?}", storage, differences);
+            assert!(actual.values().any(|state| state.locked));
+
+            if storage == StorageKind::Dense {
+                let clients: Vec<_> = String::from_utf8(output)
+                    .unwrap()
+                    .lines()
+                    .skip(1)
+                    .map(|line| line.split(',').next().unwrap().parse::<u16>().unwrap())
+                    .collect();
+                assert!(clients.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+        }
+    }
 }
@@ -0,0 +1,374 @@
+use {
+    crate::{
+        input,
+        window::{RollingWindow, Window},
+        Amount, ClientId, Moment,
+    },
+    std::{
+        collections::{HashMap, HashSet},
+        io::{self, Read},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+const CLIENT: &str = "client";
+
+/// Every column of a limits file.
+const COLUMNS: [&str; 6] = [
+    CLIENT,
+    "max_withdrawal",
+    "max_withdrawn",
+    "window",
+    "max_balance",
+    "max_open_disputes",
+];
+
+/// The `client` of the row that applies to every client without a row of its own.
+const DEFAULT_CLIENT: &str = "*";
+
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+    #[error("Missing required column \"client\"")]
+    MissingClientColumn,
+    #[error("Unknown column \"{0}\"")]
+    UnknownColumn(String),
+    #[error("Invalid client \"{0}\", expected a client ID or \"*\"")]
+    InvalidClient(String),
+    #[error("More than one row for client \"{0}\"")]
+    DuplicateClient(String),
+    #[error("Invalid {column}: \"{value}\"")]
+    InvalidValue { column: &'static str, value: String },
+}
+
+/// Rules an account must stay within. Operations that would break a rule are rejected before
+/// anything changes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest amount of a single withdrawal or withdrawal request.
+    pub max_withdrawal: Option<Amount>,
+    /// Largest amount withdrawn or requested within `window`.
+    pub max_withdrawn: Option<(Amount, Window)>,
+    /// Largest total funds, which deposits can't take the account past.
+    pub max_balance: Option<Amount>,
+    /// Largest number of deposits disputed at the same time.
+    pub max_open_disputes: Option<usize>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These limits, falling back to `defaults` for every rule that isn't set.
+    fn or(self, defaults: Self) -> Self {
+        Self {
+            max_withdrawal: self.max_withdrawal.or(defaults.max_withdrawal),
+            max_withdrawn: self.max_withdrawn.or(defaults.max_withdrawn),
+            max_balance: self.max_balance.or(defaults.max_balance),
+            max_open_disputes: self.max_open_disputes.or(defaults.max_open_disputes),
+        }
+    }
+}
+
+/// The limits of every client, read from a CSV file such as:
+///
+/// ```text
+/// client, max_withdrawal, max_withdrawn, window,        max_balance, max_open_disputes
+/// *,      1000,           5000,          seconds:86400, ,            3
+/// 7,      ,               ,              ,              250000,
+/// ```
+///
+/// The row for client `*` holds the defaults. Rules left empty for a client fall back to them.
+#[derive(Debug, Default, Clone)]
+pub struct LimitConfig {
+    defaults: Limits,
+    clients: HashMap<ClientId, Limits>,
+}
+
+impl LimitConfig {
+    pub fn new(defaults: Limits) -> Self {
+        Self {
+            defaults,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Overrides the default limits of a client, rule by rule.
+    pub fn with_client(mut self, client: ClientId, limits: Limits) -> Self {
+        self.clients.insert(client, limits);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defaults.is_empty() && self.clients.values().all(Limits::is_empty)
+    }
+
+    /// Whether any limit is over a window in seconds, which needs the input's timestamps.
+    pub fn uses_timestamps(&self) -> bool {
+        std::iter::once(&self.defaults)
+            .chain(self.clients.values())
+            .any(|limits| matches!(limits.max_withdrawn, Some((_, Window::Seconds(_)))))
+    }
+
+    /// The limits that apply to a client.
+    pub fn limits(&self, client: ClientId) -> Limits {
+        match self.clients.get(&client) {
+            Some(limits) => limits.or(self.defaults),
+            None => self.defaults,
+        }
+    }
+
+    pub fn load(reader: impl Read) -> Result<Self, LimitError> {
+        let mut reader = input::csv_reader(reader);
+        let headers = reader.headers()?.clone();
+        if let Some(unknown) = headers.iter().find(|header| !COLUMNS.contains(header)) {
+            return Err(LimitError::UnknownColumn(unknown.to_owned()));
+        }
+        if !headers.iter().any(|header| header == CLIENT) {
+            return Err(LimitError::MissingClientColumn);
+        }
+
+        let mut config = Self::default();
+        let mut seen = HashSet::new();
+        for record in reader.records() {
+            let record = record?;
+            if record.iter().all(str::is_empty) {
+                continue;
+            }
+            let fields: HashMap<_, _> = headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            let limits = parse_limits(&fields)?;
+
+            let client = fields.get(CLIENT).copied().unwrap_or_default();
+            if !seen.insert(client.to_owned()) {
+                return Err(LimitError::DuplicateClient(client.to_owned()));
+            }
+            if client == DEFAULT_CLIENT {
+                config.defaults = limits;
+            } else {
+                let client = client
+                    .parse()
+                    .map_err(|_| LimitError::InvalidClient(client.to_owned()))?;
+                config.clients.insert(client, limits);
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_limits(fields: &HashMap<&str, &str>) -> Result<Limits, LimitError> {
+    fn parse<T: FromStr>(
+        fields: &HashMap<&str, &str>,
+        column: &'static str,
+    ) -> Result<Option<T>, LimitError> {
+        fields
+            .get(column)
+            .map(|value| {
+                value.parse().map_err(|_| LimitError::InvalidValue {
+                    column,
+                    value: (*value).to_owned(),
+                })
+            })
+            .transpose()
+    }
+
+    let max_withdrawn = match (
+        parse::<Amount>(fields, "max_withdrawn")?,
+        parse::<Window>(fields, "window")?,
+    ) {
+        (Some(amount), Some(window)) => Some((amount, window)),
+        (None, None) => None,
+        (Some(amount), None) => {
+            return Err(LimitError::InvalidValue {
+                column: "max_withdrawn",
+                value: format!("{} without a window", amount),
+            })
+        }
+        (None, Some(window)) => {
+            return Err(LimitError::InvalidValue {
+                column: "window",
+                value: format!("{} without max_withdrawn", window),
+            })
+        }
+    };
+
+    Ok(Limits {
+        max_withdrawal: parse(fields, "max_withdrawal")?,
+        max_withdrawn,
+        max_balance: parse(fields, "max_balance")?,
+        max_open_disputes: parse(fields, "max_open_disputes")?,
+    })
+}
+
+/// The limits of an account, along with its recent withdrawals for checking rolling limits.
+#[derive(Debug, Clone)]
+pub(crate) struct AccountLimits {
+    limits: Limits,
+    /// The moment of the operation currently being applied.
+    now: Moment,
+    /// Only kept while there is a rolling limit.
+    withdrawals: Option<RollingWindow<Amount>>,
+}
+
+impl AccountLimits {
+    pub(crate) fn new(limits: Limits) -> Self {
+        let mut account_limits = Self {
+            limits: Limits::default(),
+            now: Moment::default(),
+            withdrawals: None,
+        };
+        account_limits.set(limits);
+        account_limits
+    }
+
+    pub(crate) fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Replaces the limits, keeping the recent withdrawals as long as there is a rolling limit.
+    pub(crate) fn set(&mut self, limits: Limits) {
+        self.limits = limits;
+        match (limits.max_withdrawn, &mut self.withdrawals) {
+            (Some((_, window)), Some(withdrawals)) => withdrawals.set_window(window),
+            (Some((_, window)), None) => self.withdrawals = Some(RollingWindow::new(window)),
+            (None, _) => self.withdrawals = None,
+        }
+    }
+
+    /// Moves to the moment of the next operation, forgetting the withdrawals that are out of the
+    /// window at `horizon`.
+    pub(crate) fn advance(&mut self, now: Moment, horizon: Moment) {
+        self.now = now;
+        if let Some(withdrawals) = &mut self.withdrawals {
+            withdrawals.prune(horizon);
+        }
+    }
+
+    /// Sum of the withdrawals still in the window.
+    pub(crate) fn withdrawn(&self) -> Amount {
+        self.withdrawals
+            .as_ref()
+            .map_or_else(Amount::default, |withdrawals| {
+                withdrawals
+                    .values(self.now)
+                    .fold(Amount::default(), |sum, amount| sum.saturating_add(*amount))
+            })
+    }
+
+    pub(crate) fn record_withdrawal(&mut self, amount: Amount) {
+        if let Some(withdrawals) = &mut self.withdrawals {
+            withdrawals.push(self.now, amount);
+        }
+    }
+
+    pub(crate) fn recorded(&self) -> u64 {
+        self.withdrawals.as_ref().map_or(0, RollingWindow::recorded)
+    }
+
+    /// The withdrawals kept for the rolling limit, with when they were made.
+    pub(crate) fn withdrawals(&self) -> impl Iterator<Item = (Moment, Amount)> + '_ {
+        self.withdrawals
+            .iter()
+            .flat_map(|withdrawals| withdrawals.entries())
+            .map(|(then, amount)| (then, *amount))
+    }
+
+    /// Records a withdrawal made at `then`, such as one restored from a snapshot, if there is a
+    /// rolling limit.
+    pub(crate) fn restore_withdrawal(&mut self, then: Moment, amount: Amount) {
+        if let Some(withdrawals) = &mut self.withdrawals {
+            withdrawals.push(then, amount);
+        }
+    }
+
+    /// Forgets the withdrawals recorded after the first `recorded`.
+    pub(crate) fn undo(&mut self, recorded: u64) {
+        if let Some(withdrawals) = &mut self.withdrawals {
+            withdrawals.undo(recorded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rust_decimal_macros::dec};
+
+    #[test]
+    fn loads_default_and_client_limits() {
+        let config = "\
+            client, max_withdrawal, max_withdrawn, window,        max_balance, max_open_disputes
+            *,      1000,           5000,          seconds:86400, ,            3
+            7,      ,               ,              ,              250000,
+            8,      10,             20,            events:5,      ,
+        ";
+        let config = LimitConfig::load(config.as_bytes()).unwrap();
+
+        let defaults = Limits {
+            max_withdrawal: Some(dec!(1000).into()),
+            max_withdrawn: Some((dec!(5000).into(), Window::Seconds(86400))),
+            max_balance: None,
+            max_open_disputes: Some(3),
+        };
+        assert_eq!(config.limits(1.into()), defaults);
+        assert_eq!(
+            config.limits(7.into()),
+            Limits {
+                max_balance: Some(dec!(250000).into()),
+                ..defaults
+            }
+        );
+        assert_eq!(
+            config.limits(8.into()),
+            Limits {
+                max_withdrawal: Some(dec!(10).into()),
+                max_withdrawn: Some((dec!(20).into(), Window::Events(5))),
+                ..defaults
+            }
+        );
+
+        for invalid in [
+            "client,max_withdrawl\n*,10\n",
+            "max_withdrawal\n10\n",
+            "client,max_withdrawal\nseven,10\n",
+            "client,max_withdrawal\n7,ten\n",
+            "client,max_withdrawn\n7,10\n",
+            "client,max_balance\n7,10\n7,20\n",
+        ] {
+            assert!(
+                LimitConfig::load(invalid.as_bytes()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn withdrawals_leave_the_window() {
+        let at = |events| Moment { events, time: 0 };
+        let mut limits = AccountLimits::new(Limits {
+            max_withdrawn: Some((dec!(100).into(), Window::Events(3))),
+            ..Limits::default()
+        });
+        for (events, amount) in [(0, dec!(10)), (1, dec!(20)), (2, dec!(40))] {
+            limits.advance(at(events), at(events));
+            limits.record_withdrawal(amount.into());
+        }
+        assert_eq!(limits.withdrawn(), dec!(70).into());
+
+        // A batch that started at event 3 moves on to event 4, then is rolled back.
+        limits.advance(at(4), at(3));
+        assert_eq!(limits.withdrawn(), dec!(40).into());
+        limits.record_withdrawal(dec!(5).into());
+        limits.undo(3);
+        limits.advance(at(3), at(3));
+        assert_eq!(limits.withdrawn(), dec!(60).into());
+    }
+}
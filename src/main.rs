@@ -1,27 +1,449 @@
 use {
-    log::error,
-    std::{env, fs::File, io, process},
+    clap::{Args, Parser, Subcommand},
+    engine::{
+        clearing::ClearingPolicy,
+        dedup::Deduplicator,
+        diff::{self, DiffError},
+        engine::Engine,
+        generate::{self, AmountDistribution, Generator, GeneratorConfig},
+        input::{self, HeaderAlias, HeaderMapping, InputStats, Strictness},
+        limits::{LimitConfig, LimitError},
+        output::{Column, OutputFormat},
+        risk::{RiskRuleError, RiskRules},
+        snapshot::SnapshotError,
+        store::StorageKind,
+        ClientId, Options,
+    },
+    log::{error, warn},
+    std::{
+        fmt::Display,
+        fs::{self, File},
+        io::{self, Read},
+        path::Path,
+        process,
+        time::Instant,
+    },
 };
 
 const DEFAULT_FILE: &str = "transactions.csv";
+const STDIN: &str = "-";
 
-fn main() {
-    env_logger::init();
+#[derive(Debug, Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Arguments of the `process` command, which runs when no command is given.
+    #[clap(flatten)]
+    process: ProcessArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Process transactions and print the final accounts state. This is the default.
+    Process(ProcessArgs),
+    /// Parse transactions without applying them and report every malformed row.
+    Validate(InputArgs),
+    /// Summarise transactions as JSON without applying them.
+    Stats(InputArgs),
+    /// Process transactions and print the final state and history of a single client.
+    Inspect {
+        #[clap(long)]
+        client: ClientId,
+        #[clap(flatten)]
+        input: InputArgs,
+    },
+    /// Compare two accounts states written by `process`.
+    Diff { left: String, right: String },
+    /// Generate random transactions with coherent lifecycles and write them to stdout.
+    Generate(GenerateArgs),
+}
+
+#[derive(Debug, Args)]
+struct InputArgs {
+    /// CSV files of transactions to read in order. Use "-" to read from stdin.
+    #[clap(default_value = DEFAULT_FILE)]
+    paths: Vec<String>,
+    /// Skip input files that don't exist instead of failing.
+    #[clap(long)]
+    skip_missing: bool,
+    /// Accept another header for a field, such as client_id=client. Can be repeated.
+    #[clap(long)]
+    header_alias: Vec<HeaderAlias>,
+    /// Reject unknown columns, and every row that doesn't have exactly the fields its type
+    /// requires, with the type written in lowercase and without surrounding whitespace.
+    #[clap(long)]
+    strict: bool,
+}
 
-    let path = env::args()
-        .nth(1)
-        .unwrap_or_else(|| String::from(DEFAULT_FILE));
+impl InputArgs {
+    fn header_mapping(&self) -> HeaderMapping {
+        let strictness = if self.strict {
+            Strictness::Strict
+        } else {
+            Strictness::Lenient
+        };
+        self.header_alias.iter().cloned().fold(
+            HeaderMapping::default().with_strictness(strictness),
+            HeaderMapping::with_alias,
+        )
+    }
+}
+
+#[derive(Debug, Args)]
+struct ProcessArgs {
+    #[clap(flatten)]
+    input: InputArgs,
+    /// Format of the final accounts state: csv, json or ndjson.
+    #[clap(long, default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+    /// Comma-separated columns of the final accounts state: client, available, held, reserved,
+    /// pending, total, locked, frozen, lock_reason, lock_cause, lock_tx and lock_sequence.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "client,available,held,total,locked"
+    )]
+    columns: Vec<Column>,
+    /// Write a ledger of every applied operation as CSV to this file.
+    #[clap(long)]
+    ledger: Option<String>,
+    /// Only export the ledger of this client.
+    #[clap(long, requires = "ledger")]
+    ledger_client: Option<ClientId>,
+    /// Check invariants after every event and at the end of the run, failing on any violation.
+    #[clap(long)]
+    audit: bool,
+    /// Write a JSON summary of the run to stderr.
+    #[clap(long)]
+    summary: bool,
+    /// Write a JSON summary of the run to this file.
+    #[clap(long)]
+    summary_file: Option<String>,
+    /// Write metrics in the Prometheus text format to this file.
+    #[clap(long)]
+    metrics_file: Option<String>,
+    /// Resume the accounts of a previous run from this file, skipping the events it already
+    /// processed, and save the state back to it at the end.
+    #[clap(long)]
+    dedup_state: Option<String>,
+    /// How to store accounts: map, dense (sorted output, fixed memory for every possible client)
+    /// or auto.
+    #[clap(long, default_value_t = StorageKind::Auto)]
+    account_storage: StorageKind,
+    /// When deposits become available: immediate, events:N after N more events, seconds:N once
+    /// the timestamp column is N seconds past the deposit, or explicit on a clear event.
+    #[clap(long, default_value_t = ClearingPolicy::Immediate)]
+    clearing: ClearingPolicy,
+    /// Hold accounts to the limits in this CSV file, with a row per client and a row for client
+    /// "*" holding the defaults.
+    #[clap(long)]
+    limits: Option<String>,
+    /// Lock or freeze accounts automatically on the rules in this CSV file, with a rule and an
+    /// action, lock or freeze, per row.
+    #[clap(long)]
+    risk_rules: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    /// Seed of the random number generator.
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// Number of events to generate.
+    #[clap(long, default_value_t = 1000)]
+    events: usize,
+    /// Number of clients.
+    #[clap(long, default_value_t = 100)]
+    clients: u16,
+    /// Chance that a deposit is later disputed.
+    #[clap(long, default_value_t = 0.05)]
+    dispute_rate: f64,
+    /// Chance that a dispute ends in a chargeback rather than a resolution.
+    #[clap(long, default_value_t = 0.3)]
+    chargeback_rate: f64,
+    /// Chance that a transaction is a withdrawal rather than a deposit.
+    #[clap(long, default_value_t = 0.3)]
+    withdrawal_rate: f64,
+    /// Distribution of amounts: uniform:MIN:MAX or exponential:MEAN.
+    #[clap(long, default_value_t = AmountDistribution::default())]
+    amounts: AmountDistribution,
+    /// Write the accounts state that processing the generated events should produce to this file.
+    #[clap(long)]
+    expected: Option<String>,
+}
+
+/// Logs an error and exits if `result` failed.
+fn or_exit<T, E: Display>(result: Result<T, E>, context: impl FnOnce() -> String) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{}: {}", context(), e);
+        process::exit(1);
+    })
+}
 
-    match File::open(&path) {
-        Ok(file) => {
-            if let Err(e) = engine::run(file, io::stdout()) {
-                error!("Fatal error: {}", e);
+/// Calls `f` with every input in order, along with its path unless it's stdin.
+fn for_each_input(input: &InputArgs, mut f: impl FnMut(Option<&str>, &mut dyn Read)) {
+    for path in &input.paths {
+        if path == STDIN {
+            f(None, &mut io::stdin().lock());
+            continue;
+        }
+        match File::open(path) {
+            Ok(mut file) => f(Some(path), &mut file),
+            Err(e) if input.skip_missing && e.kind() == io::ErrorKind::NotFound => {
+                warn!("Skipping \"{}\": {}", path, e)
+            }
+            Err(e) => {
+                error!("Error opening \"{}\": {}", path, e);
                 process::exit(1);
             }
         }
-        Err(e) => {
-            error!("Error opening \"{}\": {}", path, e);
-            process::exit(1);
+    }
+}
+
+/// Writes a file in full next to `path`, then renames it over `path`, so that a failure part way
+/// leaves the previous file untouched.
+fn replace_file(
+    path: &str,
+    write: impl FnOnce(&mut File) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    let temporary = format!("{}.tmp", path);
+    let result = File::create(&temporary).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+fn read_all(engine: &mut Engine, input: &InputArgs) {
+    for_each_input(input, |source, reader| {
+        or_exit(engine.read_source(source, reader), || {
+            String::from("Fatal error")
+        });
+    });
+    or_exit(engine.audit(), || String::from("Fatal error"));
+}
+
+fn process(args: ProcessArgs) {
+    let limits = match &args.limits {
+        Some(path) => or_exit(
+            File::open(path)
+                .map_err(LimitError::from)
+                .and_then(LimitConfig::load),
+            || format!("Error reading limits from \"{}\"", path),
+        ),
+        None => LimitConfig::default(),
+    };
+    let risk_rules = match &args.risk_rules {
+        Some(path) => or_exit(
+            File::open(path)
+                .map_err(RiskRuleError::from)
+                .and_then(RiskRules::load),
+            || format!("Error reading risk rules from \"{}\"", path),
+        ),
+        None => RiskRules::default(),
+    };
+    let options = Options {
+        output_format: args.output_format,
+        columns: args.columns.clone(),
+        ledger: args.ledger.is_some(),
+        audit: args.audit,
+        metrics: args.metrics_file.is_some(),
+        storage: args.account_storage,
+        header_mapping: args.input.header_mapping(),
+        clearing: args.clearing,
+        limits,
+        risk_rules,
+    };
+
+    let start = Instant::now();
+    let mut engine = options.engine();
+
+    if let Some(path) = &args.dedup_state {
+        engine = engine.with_deduplicator(Deduplicator::new());
+        if Path::new(path).exists() {
+            or_exit(
+                File::open(path)
+                    .map_err(SnapshotError::from)
+                    .and_then(|file| engine.load_snapshot(file)),
+                || format!("Error reading state from \"{}\"", path),
+            );
+        }
+    }
+
+    read_all(&mut engine, &args.input);
+    or_exit(
+        engine.write_accounts(
+            options
+                .output_format
+                .formatter_with_columns(&options.columns, io::stdout())
+                .as_mut(),
+        ),
+        || String::from("Error writing accounts"),
+    );
+
+    if let Some(path) = &args.dedup_state {
+        or_exit(
+            replace_file(path, |file| engine.save_snapshot(file)),
+            || format!("Error writing state to \"{}\"", path),
+        );
+    }
+    if let (Some(path), Some(ledger)) = (&args.ledger, engine.ledger()) {
+        or_exit(
+            File::create(path).and_then(|file| ledger.write_csv(file, args.ledger_client)),
+            || format!("Error writing ledger to \"{}\"", path),
+        );
+    }
+    if let Some(path) = &args.metrics_file {
+        or_exit(
+            File::create(path).and_then(|file| engine.write_metrics(file)),
+            || format!("Error writing metrics to \"{}\"", path),
+        );
+    }
+
+    let summary = engine.summary(start.elapsed());
+    if args.summary {
+        or_exit(summary.write_json(io::stderr()), || {
+            String::from("Error writing summary")
+        });
+    }
+    if let Some(path) = &args.summary_file {
+        or_exit(
+            File::create(path).and_then(|file| summary.write_json(file)),
+            || format!("Error writing summary to \"{}\"", path),
+        );
+    }
+}
+
+fn validate(args: InputArgs) {
+    let mapping = args.header_mapping();
+    let mut invalid_rows = 0;
+    for_each_input(&args, |source, reader| {
+        let invalid = or_exit(input::validate(source, reader, &mapping), || {
+            String::from("Error reading input")
+        });
+        for row in &invalid {
+            println!("{}: {}", row.location, row.error);
         }
+        invalid_rows += invalid.len();
+    });
+    if invalid_rows > 0 {
+        process::exit(1);
+    }
+}
+
+fn stats(args: InputArgs) {
+    let mapping = args.header_mapping();
+    let mut stats = InputStats::default();
+    for_each_input(&args, |_, reader| {
+        or_exit(stats.read(reader, &mapping), || {
+            String::from("Error reading input")
+        });
+    });
+    or_exit(stats.write_json(io::stdout()), || {
+        String::from("Error writing stats")
+    });
+}
+
+fn inspect(client: ClientId, args: InputArgs) {
+    let mut engine = Engine::new()
+        .with_ledger()
+        .with_header_mapping(args.header_mapping());
+    read_all(&mut engine, &args);
+
+    let account = engine.account(client).unwrap_or_else(|| {
+        error!("Client {} has no account", client);
+        process::exit(1);
+    });
+    let columns = [
+        Column::Client,
+        Column::Available,
+        Column::Held,
+        Column::Reserved,
+        Column::Pending,
+        Column::Total,
+        Column::Locked,
+        Column::Frozen,
+        Column::LockReason,
+        Column::LockCause,
+        Column::LockTransaction,
+        Column::LockSequence,
+    ];
+    let mut formatter = OutputFormat::Csv.formatter_with_columns(&columns, io::stdout());
+    or_exit(
+        formatter
+            .begin()
+            .and_then(|()| formatter.write_account(client, account))
+            .and_then(|()| formatter.finish()),
+        || String::from("Error writing account"),
+    );
+    println!();
+    if let Some(ledger) = engine.ledger() {
+        or_exit(ledger.write_csv(io::stdout(), Some(client)), || {
+            String::from("Error writing ledger")
+        });
+    }
+}
+
+fn compare(left: &str, right: &str) {
+    let read = |path: &str| {
+        or_exit(
+            File::open(path)
+                .map_err(DiffError::from)
+                .and_then(diff::read_accounts_state),
+            || format!("Error reading \"{}\"", path),
+        )
+    };
+    let differences = diff::diff(&read(left), &read(right));
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if !differences.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn generate(args: GenerateArgs) {
+    let config = GeneratorConfig {
+        seed: args.seed,
+        events: args.events,
+        clients: args.clients,
+        dispute_rate: args.dispute_rate,
+        chargeback_rate: args.chargeback_rate,
+        withdrawal_rate: args.withdrawal_rate,
+        amounts: args.amounts,
+    };
+    let mut generator = or_exit(Generator::new(config), || {
+        String::from("Invalid configuration")
+    });
+    or_exit(
+        generate::write_events(io::stdout().lock(), generator.by_ref()),
+        || String::from("Error writing events"),
+    );
+    if let Some(path) = &args.expected {
+        or_exit(
+            File::create(path)
+                .and_then(|file| diff::write_accounts_state(file, &generator.expected_state())),
+            || format!("Error writing expected state to \"{}\"", path),
+        );
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let cli = Cli::parse();
+    match cli.command {
+        None => process(cli.process),
+        Some(Command::Process(args)) => process(args),
+        Some(Command::Validate(args)) => validate(args),
+        Some(Command::Stats(args)) => stats(args),
+        Some(Command::Inspect { client, input }) => inspect(client, input),
+        Some(Command::Diff { left, right }) => compare(&left, &right),
+        Some(Command::Generate(args)) => generate(args),
     }
 }
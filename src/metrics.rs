@@ -0,0 +1,172 @@
+use {
+    crate::event::EventKind,
+    std::{
+        collections::HashMap,
+        io::{self, Write},
+        time::Duration,
+    },
+};
+
+/// Upper bounds, in seconds, of the event processing latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 9] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01,
+];
+
+/// Cumulative histogram in the style of Prometheus.
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// Point in time values of the engine state, gathered when the metrics are rendered.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Gauges {
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    pub active_disputes: u64,
+    pub deposit_history_size: u64,
+}
+
+/// Metrics that can only be measured while events are being handled.
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    latency: HashMap<EventKind, Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe_latency(&mut self, kind: EventKind, latency: Duration) {
+        self.latency.entry(kind).or_default().observe(latency);
+    }
+
+    pub fn latency(&self, kind: EventKind) -> Option<&Histogram> {
+        self.latency.get(&kind)
+    }
+
+    pub(crate) fn write_latency(&self, mut writer: impl Write) -> Result<(), io::Error> {
+        writeln!(
+            writer,
+            "# HELP engine_event_duration_seconds Time taken to handle a single event."
+        )?;
+        writeln!(writer, "# TYPE engine_event_duration_seconds histogram")?;
+        for kind in EventKind::ALL.iter() {
+            let histogram = match self.latency.get(kind) {
+                Some(histogram) => histogram,
+                None => continue,
+            };
+            for (bucket, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+                writeln!(
+                    writer,
+                    r#"engine_event_duration_seconds_bucket{{type="{}",le="{}"}} {}"#,
+                    kind, bound, bucket
+                )?;
+            }
+            writeln!(
+                writer,
+                r#"engine_event_duration_seconds_bucket{{type="{}",le="+Inf"}} {}"#,
+                kind, histogram.count
+            )?;
+            writeln!(
+                writer,
+                r#"engine_event_duration_seconds_sum{{type="{}"}} {}"#,
+                kind, histogram.sum
+            )?;
+            writeln!(
+                writer,
+                r#"engine_event_duration_seconds_count{{type="{}"}} {}"#,
+                kind, histogram.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn write_counter(
+    mut writer: impl Write,
+    name: &str,
+    help: &str,
+    values: impl IntoIterator<Item = (Option<(&'static str, String)>, u64)>,
+) -> Result<(), io::Error> {
+    writeln!(writer, "# HELP {} {}", name, help)?;
+    writeln!(writer, "# TYPE {} counter", name)?;
+    for (label, value) in values {
+        if let Some((key, label)) = label {
+            writeln!(writer, r#"{}{{{}="{}"}} {}"#, name, key, label, value)?;
+        } else {
+            writeln!(writer, "{} {}", name, value)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn write_gauges(mut writer: impl Write, gauges: Gauges) -> Result<(), io::Error> {
+    let values = [
+        (
+            "engine_accounts",
+            "Number of known accounts.",
+            gauges.accounts,
+        ),
+        (
+            "engine_locked_accounts",
+            "Number of locked accounts.",
+            gauges.locked_accounts,
+        ),
+        (
+            "engine_active_disputes",
+            "Number of deposits that are currently disputed.",
+            gauges.active_disputes,
+        ),
+        (
+            "engine_deposit_history_size",
+            "Number of deposits kept in the history of every account.",
+            gauges.deposit_history_size,
+        ),
+    ];
+    for (name, help, value) in values.iter() {
+        writeln!(writer, "# HELP {} {}", name, help)?;
+        writeln!(writer, "# TYPE {} gauge", name)?;
+        writeln!(writer, "{} {}", name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+
+        histogram.observe(Duration::from_nanos(500));
+        histogram.observe(Duration::from_micros(20));
+        histogram.observe(Duration::from_secs(1));
+
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[3], 2);
+        assert_eq!(histogram.buckets[LATENCY_BUCKETS.len() - 1], 2);
+    }
+}
@@ -0,0 +1,290 @@
+use {
+    crate::{
+        account::{AccountError, Lock},
+        event::Event,
+        Amount, ClientId, TransactionId,
+    },
+    std::fmt,
+};
+
+/// Callbacks for reacting to changes in engine state.
+///
+/// Every method has an empty default implementation, so implementors only need to override the
+/// notifications they're interested in. Notifications are delivered synchronously, in the order
+/// that the changes happen.
+pub trait EngineObserver {
+    /// A client was seen for the first time and an account was opened for it.
+    fn account_created(&mut self, _client: ClientId) {}
+
+    /// An event was successfully applied to its account.
+    fn event_applied(&mut self, _event: &Event) {}
+
+    /// An event was skipped because it had already been processed.
+    fn duplicate_skipped(&mut self, _event: &Event) {}
+
+    /// An event could not be applied to its account and was ignored.
+    fn event_rejected(&mut self, _event: &Event, _error: &AccountError) {}
+
+    /// An account was locked, either by an event or directly, as recorded in `lock`.
+    fn account_locked(&mut self, _client: ClientId, _lock: &Lock) {}
+
+    /// An account was frozen by an event, so that funds can no longer leave it.
+    fn account_frozen(&mut self, _client: ClientId, _lock: &Lock) {}
+
+    /// A deposit was disputed and its amount moved to held funds.
+    fn dispute_opened(
+        &mut self,
+        _client: ClientId,
+        _transaction_id: TransactionId,
+        _amount: Amount,
+    ) {
+    }
+
+    /// A disputed deposit was resolved and its amount released back to available funds.
+    fn dispute_resolved(
+        &mut self,
+        _client: ClientId,
+        _transaction_id: TransactionId,
+        _amount: Amount,
+    ) {
+    }
+
+    /// A disputed deposit was charged back and its amount removed from the account.
+    fn charged_back(&mut self, _client: ClientId, _transaction_id: TransactionId, _amount: Amount) {
+    }
+
+    /// A pending deposit cleared and its amount became available, either because of a `clear`
+    /// event or because it came due.
+    fn deposit_cleared(
+        &mut self,
+        _client: ClientId,
+        _transaction_id: TransactionId,
+        _amount: Amount,
+    ) {
+    }
+
+    /// A batch failed and the given number of events that had already been applied were undone.
+    fn batch_rolled_back(&mut self, _events_undone: usize) {}
+}
+
+/// The observers registered with an engine.
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Box<dyn EngineObserver>>);
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Box<dyn EngineObserver>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn notify(&mut self, mut f: impl FnMut(&mut dyn EngineObserver)) {
+        for observer in &mut self.0 {
+            f(observer.as_mut());
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{account::LockReason, engine::Engine, event::EventData},
+        rust_decimal_macros::dec,
+        std::{cell::RefCell, rc::Rc},
+    };
+
+    #[derive(Debug, PartialEq)]
+    enum Notification {
+        AccountCreated(ClientId),
+        EventApplied(Event),
+        EventRejected(Event, &'static str),
+        AccountLocked(ClientId, Lock),
+        DisputeOpened(ClientId, TransactionId, Amount),
+        DisputeResolved(ClientId, TransactionId, Amount),
+        ChargedBack(ClientId, TransactionId, Amount),
+    }
+
+    #[derive(Default, Clone)]
+    struct RecordingObserver(Rc<RefCell<Vec<Notification>>>);
+
+    impl EngineObserver for RecordingObserver {
+        fn account_created(&mut self, client: ClientId) {
+            self.0
+                .borrow_mut()
+                .push(Notification::AccountCreated(client));
+        }
+
+        fn event_applied(&mut self, event: &Event) {
+            self.0.borrow_mut().push(Notification::EventApplied(*event));
+        }
+
+        fn event_rejected(&mut self, event: &Event, error: &AccountError) {
+            self.0
+                .borrow_mut()
+                .push(Notification::EventRejected(*event, error.variant()));
+        }
+
+        fn account_locked(&mut self, client: ClientId, lock: &Lock) {
+            self.0
+                .borrow_mut()
+                .push(Notification::AccountLocked(client, *lock));
+        }
+
+        fn dispute_opened(
+            &mut self,
+            client: ClientId,
+            transaction_id: TransactionId,
+            amount: Amount,
+        ) {
+            self.0
+                .borrow_mut()
+                .push(Notification::DisputeOpened(client, transaction_id, amount));
+        }
+
+        fn dispute_resolved(
+            &mut self,
+            client: ClientId,
+            transaction_id: TransactionId,
+            amount: Amount,
+        ) {
+            self.0.borrow_mut().push(Notification::DisputeResolved(
+                client,
+                transaction_id,
+                amount,
+            ));
+        }
+
+        fn charged_back(
+            &mut self,
+            client: ClientId,
+            transaction_id: TransactionId,
+            amount: Amount,
+        ) {
+            self.0
+                .borrow_mut()
+                .push(Notification::ChargedBack(client, transaction_id, amount));
+        }
+    }
+
+    fn event(client: u16, data: EventData) -> Event {
+        Event {
+            client: ClientId::from(client),
+            data,
+        }
+    }
+
+    #[test]
+    fn notifies_every_observer() {
+        let first = RecordingObserver::default();
+        let second = RecordingObserver::default();
+        let mut engine = Engine::new()
+            .with_observer(first.clone())
+            .with_observer(second.clone());
+
+        let deposit = event(
+            1,
+            EventData::Deposit {
+                transaction_id: TransactionId::from(1),
+                amount: Amount::from(dec!(10)),
+            },
+        );
+        engine.handle_event(deposit).unwrap();
+
+        let expected = vec![
+            Notification::AccountCreated(ClientId::from(1)),
+            Notification::EventApplied(deposit),
+        ];
+        assert_eq!(*first.0.borrow(), expected);
+        assert_eq!(*second.0.borrow(), expected);
+    }
+
+    #[test]
+    fn notifies_dispute_lifecycle() {
+        let observer = RecordingObserver::default();
+        let mut engine = Engine::new().with_observer(observer.clone());
+        let tx = TransactionId::from(1);
+        let amount = Amount::from(dec!(10));
+
+        let events = [
+            event(
+                1,
+                EventData::Deposit {
+                    transaction_id: tx,
+                    amount,
+                },
+            ),
+            event(1, EventData::Dispute { transaction_id: tx }),
+            event(1, EventData::Resolve { transaction_id: tx }),
+            event(1, EventData::Dispute { transaction_id: tx }),
+            event(1, EventData::Chargeback { transaction_id: tx }),
+            event(
+                1,
+                EventData::Withdrawal {
+                    transaction_id: TransactionId::from(2),
+                    amount,
+                },
+            ),
+        ];
+        for event in events.iter() {
+            let _ = engine.handle_event(*event);
+        }
+
+        let client = ClientId::from(1);
+        let expected = vec![
+            Notification::AccountCreated(client),
+            Notification::EventApplied(events[0]),
+            Notification::EventApplied(events[1]),
+            Notification::DisputeOpened(client, tx, amount),
+            Notification::EventApplied(events[2]),
+            Notification::DisputeResolved(client, tx, amount),
+            Notification::EventApplied(events[3]),
+            Notification::DisputeOpened(client, tx, amount),
+            Notification::EventApplied(events[4]),
+            Notification::ChargedBack(client, tx, amount),
+            Notification::AccountLocked(
+                client,
+                Lock::new(LockReason::Chargeback)
+                    .with_transaction_id(tx)
+                    .with_sequence(5),
+            ),
+            Notification::EventRejected(events[5], "Withdraw::AccountLocked"),
+        ];
+        assert_eq!(*observer.0.borrow(), expected);
+    }
+
+    #[test]
+    fn notifies_accounts_locked_directly() {
+        let observer = RecordingObserver::default();
+        let mut engine = Engine::new().with_observer(observer.clone());
+        let deposit = event(
+            1,
+            EventData::Deposit {
+                transaction_id: TransactionId::from(1),
+                amount: Amount::from(dec!(10)),
+            },
+        );
+        engine.handle_event(deposit).unwrap();
+
+        let client = ClientId::from(1);
+        assert!(engine.lock_account(client));
+        assert!(!engine.lock_account(client));
+
+        let expected = vec![
+            Notification::AccountCreated(client),
+            Notification::EventApplied(deposit),
+            Notification::AccountLocked(client, Lock::new(LockReason::Admin).with_sequence(1)),
+        ];
+        assert_eq!(*observer.0.borrow(), expected);
+
+        let mut metrics = Vec::new();
+        engine.write_metrics(&mut metrics).unwrap();
+        assert!(String::from_utf8(metrics)
+            .unwrap()
+            .contains("\nengine_locked_accounts 1\n"));
+    }
+}
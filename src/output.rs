@@ -0,0 +1,433 @@
+use {
+    crate::{account::Account, ClientId},
+    derive_more::Display,
+    std::{
+        io::{self, Write},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+pub(crate) const HEADERS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+#[derive(Debug, Error)]
+#[error("Unknown output format: \"{0}\"")]
+pub struct UnknownOutputFormat(String);
+
+#[derive(Debug, Error)]
+#[error("Unknown output column: \"{0}\"")]
+pub struct UnknownColumn(String);
+
+/// A value written for every account.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    #[display(fmt = "client")]
+    Client,
+    #[display(fmt = "available")]
+    Available,
+    #[display(fmt = "held")]
+    Held,
+    /// Funds of withdrawals that were requested but not settled or cancelled yet.
+    #[display(fmt = "reserved")]
+    Reserved,
+    /// Funds of deposits that haven't cleared yet.
+    #[display(fmt = "pending")]
+    Pending,
+    #[display(fmt = "total")]
+    Total,
+    #[display(fmt = "locked")]
+    Locked,
+    /// Whether funds can't leave the account, which isn't locked though.
+    #[display(fmt = "frozen")]
+    Frozen,
+    /// Why the account was locked or frozen. This and the other lock columns are empty, or null
+    /// in JSON, unless the account is locked or frozen.
+    #[display(fmt = "lock_reason")]
+    LockReason,
+    /// Whether a chargeback, an admin or a risk rule locked the account.
+    #[display(fmt = "lock_cause")]
+    LockCause,
+    /// The transaction of the event that locked the account.
+    #[display(fmt = "lock_tx")]
+    LockTransaction,
+    /// Number of events handled when the account was locked, including the one that locked it.
+    #[display(fmt = "lock_sequence")]
+    LockSequence,
+}
+
+impl Column {
+    /// The columns written unless others are asked for.
+    pub const DEFAULT: [Self; 5] = [
+        Self::Client,
+        Self::Available,
+        Self::Held,
+        Self::Total,
+        Self::Locked,
+    ];
+
+    /// The value of the column for an account, or `None` if it has none.
+    fn value(self, client: ClientId, account: &Account) -> Option<String> {
+        let lock = account.lock();
+        match self {
+            Self::Client => Some(client.to_string()),
+            Self::Available => Some(account.available_funds().to_string()),
+            Self::Held => Some(account.held_funds().to_string()),
+            Self::Reserved => Some(account.reserved_funds().to_string()),
+            Self::Pending => Some(account.pending_funds().to_string()),
+            Self::Total => Some(account.total_funds().to_string()),
+            Self::Locked => Some(account.is_locked().to_string()),
+            Self::Frozen => Some(account.is_frozen().to_string()),
+            Self::LockReason => lock.map(|lock| lock.reason.to_string()),
+            Self::LockCause => lock.map(|lock| lock.cause().to_string()),
+            Self::LockTransaction => lock
+                .and_then(|lock| lock.transaction_id)
+                .map(|transaction_id| transaction_id.to_string()),
+            Self::LockSequence => lock
+                .and_then(|lock| lock.sequence)
+                .map(|sequence| sequence.to_string()),
+        }
+    }
+
+    /// Whether the value is written as a JSON string rather than a JSON number or boolean.
+    fn is_quoted(self) -> bool {
+        !matches!(
+            self,
+            Self::Client | Self::Locked | Self::Frozen | Self::LockTransaction | Self::LockSequence
+        )
+    }
+}
+
+impl FromStr for Column {
+    type Err = UnknownColumn;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::Client),
+            "available" => Ok(Self::Available),
+            "held" => Ok(Self::Held),
+            "reserved" => Ok(Self::Reserved),
+            "pending" => Ok(Self::Pending),
+            "total" => Ok(Self::Total),
+            "locked" => Ok(Self::Locked),
+            "frozen" => Ok(Self::Frozen),
+            "lock_reason" => Ok(Self::LockReason),
+            "lock_cause" => Ok(Self::LockCause),
+            "lock_tx" => Ok(Self::LockTransaction),
+            "lock_sequence" => Ok(Self::LockSequence),
+            unknown => Err(UnknownColumn(unknown.to_owned())),
+        }
+    }
+}
+
+/// Supported renderings of the final accounts state.
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    #[display(fmt = "csv")]
+    Csv,
+    #[display(fmt = "json")]
+    Json,
+    #[display(fmt = "ndjson")]
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = UnknownOutputFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            unknown => Err(UnknownOutputFormat(unknown.to_owned())),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn formatter<'a>(self, writer: impl Write + 'a) -> Box<dyn Formatter + 'a> {
+        self.formatter_with_columns(&Column::DEFAULT, writer)
+    }
+
+    /// A formatter that writes the given columns, in order, rather than the default ones.
+    pub fn formatter_with_columns<'a>(
+        self,
+        columns: &[Column],
+        writer: impl Write + 'a,
+    ) -> Box<dyn Formatter + 'a> {
+        match self {
+            Self::Csv => Box::new(CsvFormatter::new(writer).with_columns(columns)),
+            Self::Json => Box::new(JsonFormatter::new(writer).with_columns(columns)),
+            Self::Ndjson => Box::new(NdjsonFormatter::new(writer).with_columns(columns)),
+        }
+    }
+}
+
+/// Renders accounts one at a time to an underlying writer.
+///
+/// `begin` is called once before any accounts are written and `finish` once after the last one.
+pub trait Formatter {
+    fn begin(&mut self) -> Result<(), io::Error>;
+    fn write_account(&mut self, client: ClientId, account: &Account) -> Result<(), io::Error>;
+    fn finish(&mut self) -> Result<(), io::Error>;
+}
+
+pub struct CsvFormatter<W: Write> {
+    writer: csv::Writer<W>,
+    columns: Vec<Column>,
+}
+
+impl<W: Write> CsvFormatter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            columns: Column::DEFAULT.to_vec(),
+        }
+    }
+
+    pub fn with_columns(mut self, columns: &[Column]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+}
+
+impl<W: Write> Formatter for CsvFormatter<W> {
+    fn begin(&mut self) -> Result<(), io::Error> {
+        self.writer
+            .write_record(self.columns.iter().map(Column::to_string))?;
+        Ok(())
+    }
+
+    fn write_account(&mut self, client: ClientId, account: &Account) -> Result<(), io::Error> {
+        self.writer.write_record(
+            self.columns
+                .iter()
+                .map(|column| column.value(client, account).unwrap_or_default()),
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
+/// Writes a single JSON array of account objects.
+///
+/// Amounts are written as strings so that no precision is lost to floating point conversion.
+pub struct JsonFormatter<W: Write> {
+    writer: W,
+    columns: Vec<Column>,
+    first: bool,
+}
+
+impl<W: Write> JsonFormatter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            columns: Column::DEFAULT.to_vec(),
+            first: true,
+        }
+    }
+
+    pub fn with_columns(mut self, columns: &[Column]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+}
+
+impl<W: Write> Formatter for JsonFormatter<W> {
+    fn begin(&mut self) -> Result<(), io::Error> {
+        write!(self.writer, "[")
+    }
+
+    fn write_account(&mut self, client: ClientId, account: &Account) -> Result<(), io::Error> {
+        if !self.first {
+            write!(self.writer, ",")?;
+        }
+        self.first = false;
+        write_json_object(&mut self.writer, &self.columns, client, account)
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        writeln!(self.writer, "]")?;
+        self.writer.flush()
+    }
+}
+
+/// Writes one JSON object per line.
+pub struct NdjsonFormatter<W: Write> {
+    writer: W,
+    columns: Vec<Column>,
+}
+
+impl<W: Write> NdjsonFormatter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            columns: Column::DEFAULT.to_vec(),
+        }
+    }
+
+    pub fn with_columns(mut self, columns: &[Column]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+}
+
+impl<W: Write> Formatter for NdjsonFormatter<W> {
+    fn begin(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn write_account(&mut self, client: ClientId, account: &Account) -> Result<(), io::Error> {
+        write_json_object(&mut self.writer, &self.columns, client, account)?;
+        writeln!(self.writer)
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
+fn write_json_object(
+    mut writer: impl Write,
+    columns: &[Column],
+    client: ClientId,
+    account: &Account,
+) -> Result<(), io::Error> {
+    write!(writer, "{{")?;
+    for (i, column) in columns.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        match column.value(client, account) {
+            Some(value) if column.is_quoted() => write!(writer, r#""{}":"{}""#, column, value)?,
+            Some(value) => write!(writer, r#""{}":{}"#, column, value)?,
+            None => write!(writer, r#""{}":null"#, column)?,
+        }
+    }
+    write!(writer, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::TransactionId, rust_decimal_macros::dec};
+
+    fn render(format: OutputFormat, accounts: &[(ClientId, Account)]) -> String {
+        render_columns(format, &Column::DEFAULT, accounts)
+    }
+
+    fn render_columns(
+        format: OutputFormat,
+        columns: &[Column],
+        accounts: &[(ClientId, Account)],
+    ) -> String {
+        let mut output = Vec::new();
+        {
+            let mut formatter = format.formatter_with_columns(columns, &mut output);
+            formatter.begin().unwrap();
+            for (client, account) in accounts {
+                formatter.write_account(*client, account).unwrap();
+            }
+            formatter.finish().unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    fn accounts() -> Vec<(ClientId, Account)> {
+        let mut a = Account::new();
+        a.deposit(TransactionId::from(1), dec!(1.2300).into())
+            .unwrap();
+        let mut b = Account::new();
+        b.deposit(TransactionId::from(2), dec!(5).into()).unwrap();
+        b.dispute(TransactionId::from(2)).unwrap();
+        vec![(ClientId::from(1), a), (ClientId::from(2), b)]
+    }
+
+    #[test]
+    fn csv_output() {
+        let expected = "\
+            client,available,held,total,locked\n\
+            1,1.2300,0,1.2300,false\n\
+            2,0,5,5,false\n\
+        ";
+        assert_eq!(expected, render(OutputFormat::Csv, &accounts()));
+    }
+
+    #[test]
+    fn json_output_preserves_precision() {
+        let expected = concat!(
+            r#"[{"client":1,"available":"1.2300","held":"0","total":"1.2300","locked":false},"#,
+            r#"{"client":2,"available":"0","held":"5","total":"5","locked":false}]"#,
+            "\n",
+        );
+        assert_eq!(expected, render(OutputFormat::Json, &accounts()));
+    }
+
+    #[test]
+    fn selected_columns() {
+        let mut account = Account::new();
+        account
+            .deposit(TransactionId::from(1), dec!(10).into())
+            .unwrap();
+        account
+            .request_withdrawal(TransactionId::from(2), dec!(4).into())
+            .unwrap();
+        let accounts = [(ClientId::from(1), account)];
+        let columns = [Column::Client, Column::Reserved, Column::Total];
+
+        assert_eq!(
+            "client,reserved,total\n1,4,10\n",
+            render_columns(OutputFormat::Csv, &columns, &accounts)
+        );
+        assert_eq!(
+            concat!(r#"{"client":1,"reserved":"4","total":"10"}"#, "\n"),
+            render_columns(OutputFormat::Ndjson, &columns, &accounts)
+        );
+        assert!("balance".parse::<Column>().is_err());
+    }
+
+    #[test]
+    fn lock_columns() {
+        let mut accounts = accounts();
+        accounts[1].1.chargeback(TransactionId::from(2)).unwrap();
+        let columns = [
+            Column::Client,
+            Column::LockReason,
+            Column::LockCause,
+            Column::LockTransaction,
+            Column::LockSequence,
+        ];
+
+        assert_eq!(
+            "client,lock_reason,lock_cause,lock_tx,lock_sequence\n1,,,,\n2,chargeback,chargeback,2,\n",
+            render_columns(OutputFormat::Csv, &columns, &accounts)
+        );
+        assert_eq!(
+            concat!(
+                r#"{"client":1,"lock_reason":null,"lock_cause":null,"lock_tx":null,"lock_sequence":null}"#,
+                "\n",
+                r#"{"client":2,"lock_reason":"chargeback","lock_cause":"chargeback","lock_tx":2,"lock_sequence":null}"#,
+                "\n",
+            ),
+            render_columns(OutputFormat::Ndjson, &columns, &accounts)
+        );
+    }
+
+    #[test]
+    fn json_output_without_accounts() {
+        assert_eq!("[]\n", render(OutputFormat::Json, &[]));
+    }
+
+    #[test]
+    fn ndjson_output() {
+        let expected = concat!(
+            r#"{"client":1,"available":"1.2300","held":"0","total":"1.2300","locked":false}"#,
+            "\n",
+            r#"{"client":2,"available":"0","held":"5","total":"5","locked":false}"#,
+            "\n",
+        );
+        assert_eq!(expected, render(OutputFormat::Ndjson, &accounts()));
+    }
+}
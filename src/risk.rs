@@ -0,0 +1,567 @@
+use {
+    crate::{
+        account::{Account, Lock, LockLevel, LockReason},
+        input,
+        window::{RollingWindow, Window},
+        Amount, Moment, TransactionId,
+    },
+    derive_more::Display,
+    rust_decimal::Decimal,
+    std::{
+        collections::HashMap,
+        fmt,
+        io::{self, Read},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+const RULE: &str = "rule";
+const ACTION: &str = "action";
+
+#[derive(Debug, Error)]
+#[error("Invalid risk rule: \"{0}\"")]
+pub struct InvalidRiskRule(String);
+
+#[derive(Debug, Error)]
+#[error("Invalid risk action: \"{0}\", expected lock or freeze")]
+pub struct InvalidRiskAction(String);
+
+#[derive(Debug, Error)]
+pub enum RiskRuleError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+    #[error("Missing required column \"rule\"")]
+    MissingRuleColumn,
+    #[error("Unknown column \"{0}\"")]
+    UnknownColumn(String),
+    #[error("{0}")]
+    InvalidRule(#[from] InvalidRiskRule),
+    #[error("{0}")]
+    InvalidAction(#[from] InvalidRiskAction),
+}
+
+/// A rule that locks or freezes an account automatically once it is broken. Rules are checked
+/// after every event applied to an account that isn't locked yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskRule {
+    /// Lock after `count` disputes within the last `events` events handled by the engine. Written
+    /// as `disputes:COUNT:EVENTS`.
+    Disputes { count: usize, events: u64 },
+    /// Lock once the deposits that were ever disputed reach `ratio` of the deposits made, counting
+    /// only accounts with at least `min_deposits` deposits. Written as
+    /// `dispute_ratio:RATIO:MIN_DEPOSITS`.
+    DisputeRatio { ratio: Decimal, min_deposits: usize },
+    /// Lock as soon as the available funds are negative, which disputing spent funds can cause.
+    /// Written as `negative_balance`.
+    NegativeBalance,
+}
+
+impl RiskRule {
+    /// The reason recorded on accounts this rule locks.
+    pub fn reason(self) -> LockReason {
+        match self {
+            Self::Disputes { .. } => LockReason::Disputes,
+            Self::DisputeRatio { .. } => LockReason::DisputeRatio,
+            Self::NegativeBalance => LockReason::NegativeBalance,
+        }
+    }
+
+    /// Whether the rule needs the disputes of each account to be counted.
+    fn counts_disputes(self) -> bool {
+        !matches!(self, Self::NegativeBalance)
+    }
+
+    fn is_broken(self, account: &Account) -> bool {
+        match self {
+            Self::Disputes { count, events } => account
+                .dispute_activity()
+                .is_some_and(|activity| activity.recent(Window::Events(events)) >= count),
+            Self::DisputeRatio {
+                ratio,
+                min_deposits,
+            } => {
+                let deposits = account.deposit_count();
+                deposits > 0
+                    && deposits >= min_deposits
+                    && account.dispute_activity().is_some_and(|activity| {
+                        Decimal::from(activity.disputed_deposits()) / Decimal::from(deposits)
+                            >= ratio
+                    })
+            }
+            Self::NegativeBalance => account.available_funds() < Amount::default(),
+        }
+    }
+}
+
+impl FromStr for RiskRule {
+    type Err = InvalidRiskRule;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidRiskRule(s.to_owned());
+        let parts: Vec<_> = s.split(':').collect();
+        match parts.as_slice() {
+            ["disputes", count, events] => {
+                let count = count.parse().map_err(|_| invalid())?;
+                let events = events.parse().map_err(|_| invalid())?;
+                if count == 0 || events == 0 {
+                    return Err(invalid());
+                }
+                Ok(Self::Disputes { count, events })
+            }
+            ["dispute_ratio", ratio, min_deposits] => {
+                let ratio: Decimal = ratio.parse().map_err(|_| invalid())?;
+                if ratio <= Decimal::ZERO {
+                    return Err(invalid());
+                }
+                Ok(Self::DisputeRatio {
+                    ratio,
+                    min_deposits: min_deposits.parse().map_err(|_| invalid())?,
+                })
+            }
+            ["negative_balance"] => Ok(Self::NegativeBalance),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for RiskRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Disputes { count, events } => write!(f, "disputes:{}:{}", count, events),
+            Self::DisputeRatio {
+                ratio,
+                min_deposits,
+            } => write!(f, "dispute_ratio:{}:{}", ratio, min_deposits),
+            Self::NegativeBalance => write!(f, "negative_balance"),
+        }
+    }
+}
+
+/// What happens to an account that breaks a rule.
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RiskAction {
+    /// Lock the account, so that it takes no more deposits or withdrawals.
+    #[default]
+    #[display(fmt = "lock")]
+    Lock,
+    /// Freeze the account, so that funds can't leave it. A freeze is never lifted, but a rule that
+    /// locks can still lock the account.
+    #[display(fmt = "freeze")]
+    Freeze,
+}
+
+impl RiskAction {
+    pub fn level(self) -> LockLevel {
+        match self {
+            Self::Lock => LockLevel::Locked,
+            Self::Freeze => LockLevel::Frozen,
+        }
+    }
+}
+
+impl FromStr for RiskAction {
+    type Err = InvalidRiskAction;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lock" => Ok(Self::Lock),
+            "freeze" => Ok(Self::Freeze),
+            _ => Err(InvalidRiskAction(s.to_owned())),
+        }
+    }
+}
+
+/// The rules an engine checks, read from a CSV file such as:
+///
+/// ```text
+/// rule,                  action
+/// dispute_ratio:0.25:8,  freeze
+/// disputes:3:1000,       lock
+/// negative_balance,
+/// ```
+///
+/// The action is `lock` unless given. Rules are checked in order, and the first one broken is
+/// recorded as the reason. A rule that locks still applies to an account that another froze.
+#[derive(Debug, Default, Clone)]
+pub struct RiskRules {
+    rules: Vec<(RiskRule, RiskAction)>,
+    /// Whether any rule needs the disputes of each account to be counted.
+    counts_disputes: bool,
+    /// The longest window of a `Disputes` rule in events, if any.
+    dispute_events: Option<u64>,
+}
+
+impl RiskRules {
+    pub fn with_rule(mut self, rule: RiskRule, action: RiskAction) -> Self {
+        self.counts_disputes |= rule.counts_disputes();
+        if let RiskRule::Disputes { events, .. } = rule {
+            self.dispute_events = self.dispute_events.max(Some(events));
+        }
+        self.rules.push((rule, action));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn rules(&self) -> &[(RiskRule, RiskAction)] {
+        &self.rules
+    }
+
+    pub fn load(reader: impl Read) -> Result<Self, RiskRuleError> {
+        let mut reader = input::csv_reader(reader);
+        let headers = reader.headers()?.clone();
+        if let Some(unknown) = headers
+            .iter()
+            .find(|header| *header != RULE && *header != ACTION)
+        {
+            return Err(RiskRuleError::UnknownColumn(unknown.to_owned()));
+        }
+        if !headers.iter().any(|header| header == RULE) {
+            return Err(RiskRuleError::MissingRuleColumn);
+        }
+
+        let mut rules = Self::default();
+        for record in reader.records() {
+            let record = record?;
+            if record.iter().all(str::is_empty) {
+                continue;
+            }
+            let fields: HashMap<_, _> = headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            let rule = fields.get(RULE).copied().unwrap_or_default().parse()?;
+            let action = fields
+                .get(ACTION)
+                .map(|action| action.parse())
+                .transpose()?
+                .unwrap_or_default();
+            rules = rules.with_rule(rule, action);
+        }
+        Ok(rules)
+    }
+
+    /// Starts counting the disputes of an account, if any rule needs them.
+    pub(crate) fn track(&self, account: &mut Account) {
+        if self.counts_disputes {
+            account.track_disputes(self.dispute_events.map(Window::Events));
+        }
+    }
+
+    /// Locks or freezes the account if it breaks a rule that would restrict it further, returning
+    /// whether it did. The lock is recorded against the transaction of the event just applied.
+    pub(crate) fn enforce(&self, account: &mut Account, transaction_id: TransactionId) -> bool {
+        if account.is_locked() {
+            return false;
+        }
+        let level = account.lock().map(|lock| lock.level);
+        let broken = self
+            .rules
+            .iter()
+            .find(|(rule, action)| level < Some(action.level()) && rule.is_broken(account));
+        match broken {
+            Some((rule, action)) => {
+                account.set_lock(
+                    Lock::new(rule.reason())
+                        .with_level(action.level())
+                        .with_transaction_id(transaction_id),
+                );
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The disputes of an account, for checking rules that count them.
+#[derive(Debug, Clone)]
+pub(crate) struct DisputeActivity {
+    /// The moment of the operation currently being applied.
+    now: Moment,
+    /// When disputes were opened, only kept while a rule counts recent disputes.
+    disputes: Option<RollingWindow<()>>,
+    /// Number of deposits that were disputed at least once.
+    disputed_deposits: usize,
+}
+
+impl DisputeActivity {
+    pub(crate) fn new(window: Option<Window>, disputed_deposits: usize) -> Self {
+        Self {
+            now: Moment::default(),
+            disputes: window.map(RollingWindow::new),
+            disputed_deposits,
+        }
+    }
+
+    /// Moves to the moment of the next operation, forgetting the disputes that are out of the
+    /// window at `horizon`.
+    pub(crate) fn advance(&mut self, now: Moment, horizon: Moment) {
+        self.now = now;
+        if let Some(disputes) = &mut self.disputes {
+            disputes.prune(horizon);
+        }
+    }
+
+    /// Number of disputes opened within `window`, which is no longer than the window kept.
+    pub(crate) fn recent(&self, window: Window) -> usize {
+        self.disputes.as_ref().map_or(0, |disputes| {
+            disputes.values_within(window, self.now).count()
+        })
+    }
+
+    pub(crate) fn disputed_deposits(&self) -> usize {
+        self.disputed_deposits
+    }
+
+    /// Records a dispute, which is the `first` one if the deposit was never disputed before.
+    pub(crate) fn record_dispute(&mut self, first: bool) {
+        if let Some(disputes) = &mut self.disputes {
+            disputes.push(self.now, ());
+        }
+        if first {
+            self.disputed_deposits += 1;
+        }
+    }
+
+    pub(crate) fn recorded(&self) -> u64 {
+        self.disputes.as_ref().map_or(0, RollingWindow::recorded)
+    }
+
+    /// When the disputes kept for rules that count recent ones were opened.
+    pub(crate) fn disputes(&self) -> impl Iterator<Item = Moment> + '_ {
+        self.disputes
+            .iter()
+            .flat_map(|disputes| disputes.entries())
+            .map(|(then, ())| then)
+    }
+
+    /// Records a dispute opened at `then`, such as one restored from a snapshot, if a rule counts
+    /// recent disputes.
+    pub(crate) fn restore_dispute(&mut self, then: Moment) {
+        if let Some(disputes) = &mut self.disputes {
+            disputes.push(then, ());
+        }
+    }
+
+    /// Forgets the disputes recorded after the first `recorded`, going back to
+    /// `disputed_deposits` deposits disputed.
+    pub(crate) fn undo(&mut self, recorded: u64, disputed_deposits: usize) {
+        if let Some(disputes) = &mut self.disputes {
+            disputes.undo(recorded);
+        }
+        self.disputed_deposits = disputed_deposits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rust_decimal_macros::dec};
+
+    #[test]
+    fn parses_risk_rules() {
+        for rule in [
+            RiskRule::Disputes {
+                count: 3,
+                events: 100,
+            },
+            RiskRule::DisputeRatio {
+                ratio: dec!(0.25),
+                min_deposits: 8,
+            },
+            RiskRule::NegativeBalance,
+        ] {
+            assert_eq!(rule.to_string().parse::<RiskRule>().unwrap(), rule);
+        }
+        for invalid in [
+            "disputes:3",
+            "disputes:0:100",
+            "dispute_ratio:-1:8",
+            "negative_balance:1",
+            "chargebacks",
+        ] {
+            assert!(invalid.parse::<RiskRule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn loads_risk_rules() {
+        let rules = RiskRules::load(
+            "\
+            rule,                  action
+            dispute_ratio:0.25:8,  freeze
+            negative_balance,
+            "
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            rules.rules(),
+            [
+                (
+                    RiskRule::DisputeRatio {
+                        ratio: dec!(0.25),
+                        min_deposits: 8,
+                    },
+                    RiskAction::Freeze,
+                ),
+                (RiskRule::NegativeBalance, RiskAction::Lock),
+            ]
+        );
+
+        for invalid in [
+            "action\nlock\n",
+            "rule,level\nnegative_balance,lock\n",
+            "rule,action\nchargebacks,lock\n",
+            "rule,action\nnegative_balance,suspend\n",
+        ] {
+            assert!(RiskRules::load(invalid.as_bytes()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn locks_accounts_that_were_frozen() {
+        let rules = RiskRules::default()
+            .with_rule(RiskRule::NegativeBalance, RiskAction::Freeze)
+            .with_rule(
+                RiskRule::DisputeRatio {
+                    ratio: dec!(1),
+                    min_deposits: 1,
+                },
+                RiskAction::Lock,
+            );
+        let mut account = Account::new();
+        rules.track(&mut account);
+        for tx in 1..=2 {
+            account
+                .deposit(TransactionId::from(tx), Amount::from(dec!(10)))
+                .unwrap();
+        }
+        account.withdraw(Amount::from(dec!(15))).unwrap();
+
+        account.dispute(TransactionId::from(1)).unwrap();
+        assert!(rules.enforce(&mut account, TransactionId::from(1)));
+        assert!(account.is_frozen());
+        assert_eq!(account.lock_reason(), Some(LockReason::NegativeBalance));
+        assert!(!rules.enforce(&mut account, TransactionId::from(1)));
+
+        account.dispute(TransactionId::from(2)).unwrap();
+        assert!(rules.enforce(&mut account, TransactionId::from(2)));
+        assert!(account.is_locked());
+        assert_eq!(
+            account.lock(),
+            Some(&Lock::new(LockReason::DisputeRatio).with_transaction_id(TransactionId::from(2)))
+        );
+    }
+
+    #[test]
+    fn locks_accounts_that_break_a_rule() {
+        let rules = RiskRules::default()
+            .with_rule(
+                RiskRule::Disputes {
+                    count: 2,
+                    events: 3,
+                },
+                RiskAction::Lock,
+            )
+            .with_rule(
+                RiskRule::DisputeRatio {
+                    ratio: dec!(0.75),
+                    min_deposits: 4,
+                },
+                RiskAction::Lock,
+            );
+        let at = |events| Moment { events, time: 0 };
+        let mut account = Account::new();
+        rules.track(&mut account);
+        for tx in 1..=4 {
+            account
+                .deposit(TransactionId::from(tx), Amount::from(dec!(10)))
+                .unwrap();
+        }
+
+        account.advance(at(0), at(0));
+        account.dispute(TransactionId::from(1)).unwrap();
+        account.resolve(TransactionId::from(1)).unwrap();
+        assert!(!rules.enforce(&mut account, TransactionId::from(1)));
+        account.advance(at(3), at(3));
+        account.dispute(TransactionId::from(2)).unwrap();
+        assert!(!rules.enforce(&mut account, TransactionId::from(2)));
+        account.advance(at(4), at(4));
+        account.dispute(TransactionId::from(3)).unwrap();
+        assert!(rules.enforce(&mut account, TransactionId::from(3)));
+        assert_eq!(
+            account.lock(),
+            Some(&Lock::new(LockReason::Disputes).with_transaction_id(TransactionId::from(3)))
+        );
+        assert!(!rules.enforce(&mut account, TransactionId::from(3)));
+    }
+
+    #[test]
+    fn dispute_ratio_counts_each_deposit_once() {
+        let rules = RiskRules::default().with_rule(
+            RiskRule::DisputeRatio {
+                ratio: dec!(0.5),
+                min_deposits: 4,
+            },
+            RiskAction::Lock,
+        );
+        let mut account = Account::new();
+        for tx in 1..=4 {
+            account
+                .deposit(TransactionId::from(tx), Amount::from(dec!(10)))
+                .unwrap();
+        }
+        account.dispute(TransactionId::from(1)).unwrap();
+        account.resolve(TransactionId::from(1)).unwrap();
+
+        // Deposits disputed before the rules applied still count, but only once.
+        rules.track(&mut account);
+        for _ in 0..3 {
+            account.dispute(TransactionId::from(1)).unwrap();
+            assert!(!rules.enforce(&mut account, TransactionId::from(1)));
+            account.resolve(TransactionId::from(1)).unwrap();
+        }
+        account.dispute(TransactionId::from(2)).unwrap();
+        assert!(rules.enforce(&mut account, TransactionId::from(2)));
+        assert_eq!(account.lock_reason(), Some(LockReason::DisputeRatio));
+    }
+
+    #[test]
+    fn rolled_back_disputes_stay_in_the_window() {
+        let rules = RiskRules::default().with_rule(
+            RiskRule::Disputes {
+                count: 2,
+                events: 3,
+            },
+            RiskAction::Lock,
+        );
+        let at = |events| Moment { events, time: 0 };
+        let mut account = Account::new();
+        rules.track(&mut account);
+        for tx in 1..=2 {
+            account
+                .deposit(TransactionId::from(tx), Amount::from(dec!(10)))
+                .unwrap();
+        }
+        account.advance(at(0), at(0));
+        account.dispute(TransactionId::from(1)).unwrap();
+
+        // A batch started at event 1 gets to event 3, where the first dispute is out of the
+        // window, and is then rolled back.
+        let checkpoint = account.checkpoint(TransactionId::from(2));
+        account.advance(at(3), at(1));
+        account.dispute(TransactionId::from(2)).unwrap();
+        assert!(!rules.enforce(&mut account, TransactionId::from(2)));
+        account.restore(checkpoint);
+
+        account.advance(at(1), at(1));
+        account.dispute(TransactionId::from(2)).unwrap();
+        assert!(rules.enforce(&mut account, TransactionId::from(2)));
+    }
+}
@@ -0,0 +1,588 @@
+use {
+    crate::{
+        account::{
+            Account, DepositState, Lock, LockLevel, LockReason, ProcessedDeposit,
+            RequestedWithdrawal, WithdrawalState,
+        },
+        audit::{Auditor, InvariantError},
+        dedup::EventKey,
+        Amount, ClientId, Moment, TransactionId,
+    },
+    csv::{ReaderBuilder, StringRecord, Trim, WriterBuilder},
+    std::{
+        collections::HashMap,
+        io::{self, Read, Write},
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+const CLOCK: &str = "clock";
+const EVENT_ID: &str = "event_id";
+const ROW: &str = "row";
+const ACCOUNT: &str = "account";
+const DEPOSIT: &str = "deposit";
+const WITHDRAWAL: &str = "withdrawal";
+const WITHDRAWN: &str = "withdrawn";
+const DISPUTED: &str = "disputed";
+const CLEARING: &str = "clearing";
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+    #[error("Unknown record \"{0}\"")]
+    UnknownRecord(String),
+    #[error("Invalid {field} in {record} record: \"{value}\"")]
+    InvalidValue {
+        record: &'static str,
+        field: &'static str,
+        value: String,
+    },
+    #[error("More than one account record for client {0}")]
+    DuplicateAccount(ClientId),
+    #[error("Record for client {0} before its account record")]
+    UnknownClient(ClientId),
+    #[error("More than one record for transaction {1} of client {0}")]
+    DuplicateTransactionId(ClientId, TransactionId),
+    #[error("Inconsistent account: {0}")]
+    InconsistentAccount(#[from] InvariantError),
+    #[error("Snapshots can only be loaded into an engine that hasn't handled any events")]
+    EngineNotEmpty,
+}
+
+/// Everything read from a snapshot, for the engine to resume from.
+///
+/// A snapshot is a CSV file without headers, where the first field of each record says what the
+/// rest of it holds:
+///
+/// ```text
+/// clock,      EVENTS, TIME
+/// event_id,   ID
+/// row,        SOURCE, ROW, CONTENT
+/// account,    CLIENT, AVAILABLE, HELD, RESERVED, PENDING, LOCK_REASON, LOCK_LEVEL, LOCK_TX,
+///             LOCK_SEQUENCE
+/// deposit,    CLIENT, TX, AMOUNT, STATE, DISPUTED
+/// withdrawal, CLIENT, TX, AMOUNT, STATE
+/// withdrawn,  CLIENT, EVENTS, TIME, AMOUNT
+/// disputed,   CLIENT, EVENTS, TIME
+/// clearing,   DUE, CLIENT, TX
+/// ```
+///
+/// `event_id` and `row` records are the events already processed, `withdrawn` and `disputed`
+/// records the withdrawals and disputes kept for rolling limits and risk rules, and `clearing`
+/// records the pending deposits waiting to come due. Every record for a client comes after its
+/// `account` record.
+#[derive(Debug, Default)]
+pub(crate) struct Snapshot {
+    pub(crate) clock: Moment,
+    pub(crate) keys: Vec<EventKey>,
+    pub(crate) accounts: Vec<(ClientId, Account)>,
+    pub(crate) withdrawn: Vec<(ClientId, Moment, Amount)>,
+    pub(crate) disputed: Vec<(ClientId, Moment)>,
+    pub(crate) clearing: Vec<(u64, ClientId, TransactionId)>,
+}
+
+impl Snapshot {
+    pub(crate) fn read(reader: impl Read) -> Result<Self, SnapshotError> {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(reader);
+
+        let mut snapshot = Self::default();
+        let mut clients = HashMap::new();
+        for record in reader.records() {
+            let record = record?;
+            let fields = Fields(&record);
+            match fields.get(0) {
+                CLOCK => {
+                    snapshot.clock = Moment {
+                        events: fields.parse(CLOCK, 1, "events")?,
+                        time: fields.parse(CLOCK, 2, "time")?,
+                    };
+                }
+                EVENT_ID => snapshot.keys.push(EventKey::Id(fields.get(1).to_owned())),
+                ROW => snapshot.keys.push(EventKey::Row {
+                    source: fields.get(1).to_owned(),
+                    row: fields.parse(ROW, 2, "row")?,
+                    content: fields.parse(ROW, 3, "content")?,
+                }),
+                ACCOUNT => {
+                    let client = fields.parse(ACCOUNT, 1, "client")?;
+                    let lock = match fields.get(6) {
+                        "" => None,
+                        reason => Some(Lock {
+                            reason: parse_lock_reason(reason)?,
+                            level: parse_lock_level(fields.get(7))?,
+                            transaction_id: fields.parse_optional(ACCOUNT, 8, "lock_tx")?,
+                            sequence: fields.parse_optional(ACCOUNT, 9, "lock_sequence")?,
+                        }),
+                    };
+                    let account = Account::restored(
+                        fields.parse(ACCOUNT, 2, "available")?,
+                        fields.parse(ACCOUNT, 3, "held")?,
+                        fields.parse(ACCOUNT, 4, "reserved")?,
+                        fields.parse(ACCOUNT, 5, "pending")?,
+                        lock,
+                    );
+                    if clients.insert(client, snapshot.accounts.len()).is_some() {
+                        return Err(SnapshotError::DuplicateAccount(client));
+                    }
+                    snapshot.accounts.push((client, account));
+                }
+                DEPOSIT => {
+                    let client = fields.parse(DEPOSIT, 1, "client")?;
+                    let transaction_id = fields.parse(DEPOSIT, 2, "tx")?;
+                    let deposit = ProcessedDeposit::restored(
+                        parse_deposit_state(fields.get(4))?,
+                        fields.parse(DEPOSIT, 5, "disputed")?,
+                        fields.parse(DEPOSIT, 3, "amount")?,
+                    );
+                    let account = account(&mut snapshot.accounts, &clients, client)?;
+                    account
+                        .restore_deposit(transaction_id, deposit)
+                        .map_err(|_| {
+                            SnapshotError::DuplicateTransactionId(client, transaction_id)
+                        })?;
+                }
+                WITHDRAWAL => {
+                    let client = fields.parse(WITHDRAWAL, 1, "client")?;
+                    let transaction_id = fields.parse(WITHDRAWAL, 2, "tx")?;
+                    let withdrawal = RequestedWithdrawal::restored(
+                        parse_withdrawal_state(fields.get(4))?,
+                        fields.parse(WITHDRAWAL, 3, "amount")?,
+                    );
+                    let account = account(&mut snapshot.accounts, &clients, client)?;
+                    account
+                        .restore_withdrawal(transaction_id, withdrawal)
+                        .map_err(|_| {
+                            SnapshotError::DuplicateTransactionId(client, transaction_id)
+                        })?;
+                }
+                WITHDRAWN => {
+                    let client = fields.parse(WITHDRAWN, 1, "client")?;
+                    account(&mut snapshot.accounts, &clients, client)?;
+                    snapshot.withdrawn.push((
+                        client,
+                        Moment {
+                            events: fields.parse(WITHDRAWN, 2, "events")?,
+                            time: fields.parse(WITHDRAWN, 3, "time")?,
+                        },
+                        fields.parse(WITHDRAWN, 4, "amount")?,
+                    ));
+                }
+                DISPUTED => {
+                    let client = fields.parse(DISPUTED, 1, "client")?;
+                    account(&mut snapshot.accounts, &clients, client)?;
+                    snapshot.disputed.push((
+                        client,
+                        Moment {
+                            events: fields.parse(DISPUTED, 2, "events")?,
+                            time: fields.parse(DISPUTED, 3, "time")?,
+                        },
+                    ));
+                }
+                CLEARING => {
+                    let client = fields.parse(CLEARING, 2, "client")?;
+                    account(&mut snapshot.accounts, &clients, client)?;
+                    snapshot.clearing.push((
+                        fields.parse(CLEARING, 1, "due")?,
+                        client,
+                        fields.parse(CLEARING, 3, "tx")?,
+                    ));
+                }
+                unknown => return Err(SnapshotError::UnknownRecord(unknown.to_owned())),
+            }
+        }
+
+        for (client, account) in &snapshot.accounts {
+            Auditor::check_balances(*client, account)?;
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Writes the records of a snapshot, in the order the engine gives them.
+pub(crate) struct SnapshotWriter<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer: WriterBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_writer(writer),
+        }
+    }
+
+    pub(crate) fn clock(&mut self, clock: Moment) -> Result<(), io::Error> {
+        self.write([CLOCK, &clock.events.to_string(), &clock.time.to_string()])
+    }
+
+    pub(crate) fn key(&mut self, key: &EventKey) -> Result<(), io::Error> {
+        match key {
+            EventKey::Id(id) => self.write([EVENT_ID, id]),
+            EventKey::Row {
+                source,
+                row,
+                content,
+            } => self.write([ROW, source, &row.to_string(), &content.to_string()]),
+        }
+    }
+
+    /// Writes an account along with its history and the withdrawals and disputes kept for it.
+    pub(crate) fn account(&mut self, client: ClientId, account: &Account) -> Result<(), io::Error> {
+        let client = client.to_string();
+        let lock = account.lock();
+        self.write([
+            ACCOUNT,
+            &client,
+            &account.available_funds().to_string(),
+            &account.held_funds().to_string(),
+            &account.reserved_funds().to_string(),
+            &account.pending_funds().to_string(),
+            &lock.map_or_else(String::new, |lock| lock.reason.to_string()),
+            &lock.map_or_else(String::new, |lock| lock.level.to_string()),
+            &lock
+                .and_then(|lock| lock.transaction_id)
+                .map_or_else(String::new, |transaction_id| transaction_id.to_string()),
+            &lock
+                .and_then(|lock| lock.sequence)
+                .map_or_else(String::new, |sequence| sequence.to_string()),
+        ])?;
+
+        let mut deposits: Vec<_> = account.processed_deposits().collect();
+        deposits.sort_unstable_by_key(|(transaction_id, _)| u32::from(*transaction_id));
+        for (transaction_id, deposit) in deposits {
+            self.write([
+                DEPOSIT,
+                &client,
+                &transaction_id.to_string(),
+                &deposit.amount().to_string(),
+                deposit_state(deposit.state()),
+                &deposit.was_disputed().to_string(),
+            ])?;
+        }
+
+        let mut withdrawals: Vec<_> = account.requested_withdrawals().collect();
+        withdrawals.sort_unstable_by_key(|(transaction_id, _)| u32::from(*transaction_id));
+        for (transaction_id, withdrawal) in withdrawals {
+            self.write([
+                WITHDRAWAL,
+                &client,
+                &transaction_id.to_string(),
+                &withdrawal.amount().to_string(),
+                withdrawal_state(withdrawal.state()),
+            ])?;
+        }
+
+        for (then, amount) in account.recent_withdrawals() {
+            self.write([
+                WITHDRAWN,
+                &client,
+                &then.events.to_string(),
+                &then.time.to_string(),
+                &amount.to_string(),
+            ])?;
+        }
+        for then in account.recent_disputes() {
+            self.write([
+                DISPUTED,
+                &client,
+                &then.events.to_string(),
+                &then.time.to_string(),
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn clearing(
+        &mut self,
+        due: u64,
+        client: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), io::Error> {
+        self.write([
+            CLEARING,
+            &due.to_string(),
+            &client.to_string(),
+            &transaction_id.to_string(),
+        ])
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+
+    fn write<const N: usize>(&mut self, record: [&str; N]) -> Result<(), io::Error> {
+        self.writer.write_record(record).map_err(io::Error::from)
+    }
+}
+
+/// The fields of a record, where missing fields read as empty.
+struct Fields<'a>(&'a StringRecord);
+
+impl Fields<'_> {
+    fn get(&self, index: usize) -> &str {
+        self.0.get(index).unwrap_or_default()
+    }
+
+    fn parse<T: FromStr>(
+        &self,
+        record: &'static str,
+        index: usize,
+        field: &'static str,
+    ) -> Result<T, SnapshotError> {
+        let value = self.get(index);
+        value.parse().map_err(|_| SnapshotError::InvalidValue {
+            record,
+            field,
+            value: value.to_owned(),
+        })
+    }
+
+    fn parse_optional<T: FromStr>(
+        &self,
+        record: &'static str,
+        index: usize,
+        field: &'static str,
+    ) -> Result<Option<T>, SnapshotError> {
+        match self.get(index) {
+            "" => Ok(None),
+            _ => self.parse(record, index, field).map(Some),
+        }
+    }
+}
+
+/// The account restored so far for a client, which must have had its account record already.
+fn account<'a>(
+    accounts: &'a mut [(ClientId, Account)],
+    clients: &HashMap<ClientId, usize>,
+    client: ClientId,
+) -> Result<&'a mut Account, SnapshotError> {
+    match clients.get(&client) {
+        Some(index) => Ok(&mut accounts[*index].1),
+        None => Err(SnapshotError::UnknownClient(client)),
+    }
+}
+
+fn invalid(record: &'static str, field: &'static str, value: &str) -> SnapshotError {
+    SnapshotError::InvalidValue {
+        record,
+        field,
+        value: value.to_owned(),
+    }
+}
+
+fn deposit_state(state: DepositState) -> &'static str {
+    match state {
+        DepositState::Pending => "pending",
+        DepositState::MaybeSettled => "settled",
+        DepositState::Disputed => "disputed",
+        DepositState::Reversed => "reversed",
+    }
+}
+
+fn parse_deposit_state(s: &str) -> Result<DepositState, SnapshotError> {
+    match s {
+        "pending" => Ok(DepositState::Pending),
+        "settled" => Ok(DepositState::MaybeSettled),
+        "disputed" => Ok(DepositState::Disputed),
+        "reversed" => Ok(DepositState::Reversed),
+        _ => Err(invalid(DEPOSIT, "state", s)),
+    }
+}
+
+fn withdrawal_state(state: WithdrawalState) -> &'static str {
+    match state {
+        WithdrawalState::Reserved => "reserved",
+        WithdrawalState::Settled => "settled",
+        WithdrawalState::Cancelled => "cancelled",
+    }
+}
+
+fn parse_withdrawal_state(s: &str) -> Result<WithdrawalState, SnapshotError> {
+    match s {
+        "reserved" => Ok(WithdrawalState::Reserved),
+        "settled" => Ok(WithdrawalState::Settled),
+        "cancelled" => Ok(WithdrawalState::Cancelled),
+        _ => Err(invalid(WITHDRAWAL, "state", s)),
+    }
+}
+
+fn parse_lock_reason(s: &str) -> Result<LockReason, SnapshotError> {
+    [
+        LockReason::Chargeback,
+        LockReason::Admin,
+        LockReason::Disputes,
+        LockReason::DisputeRatio,
+        LockReason::NegativeBalance,
+    ]
+    .iter()
+    .copied()
+    .find(|reason| reason.to_string() == s)
+    .ok_or_else(|| invalid(ACCOUNT, "lock_reason", s))
+}
+
+fn parse_lock_level(s: &str) -> Result<LockLevel, SnapshotError> {
+    [LockLevel::Frozen, LockLevel::Locked]
+        .iter()
+        .copied()
+        .find(|level| level.to_string() == s)
+        .ok_or_else(|| invalid(ACCOUNT, "lock_level", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rust_decimal_macros::dec};
+
+    #[test]
+    fn snapshot_round_trips() {
+        let mut account = Account::restored(
+            Amount::from(dec!(7.5)),
+            Amount::from(dec!(10)),
+            Amount::from(dec!(2.5)),
+            Amount::from(dec!(0)),
+            Some(
+                Lock::new(LockReason::Disputes)
+                    .with_level(LockLevel::Frozen)
+                    .with_transaction_id(2.into())
+                    .with_sequence(3),
+            ),
+        );
+        account
+            .restore_deposit(
+                2.into(),
+                ProcessedDeposit::restored(DepositState::Disputed, true, Amount::from(dec!(10))),
+            )
+            .unwrap();
+        account
+            .restore_deposit(
+                1.into(),
+                ProcessedDeposit::restored(
+                    DepositState::MaybeSettled,
+                    false,
+                    Amount::from(dec!(20)),
+                ),
+            )
+            .unwrap();
+        account
+            .restore_withdrawal(
+                3.into(),
+                RequestedWithdrawal::restored(WithdrawalState::Reserved, Amount::from(dec!(2.5))),
+            )
+            .unwrap();
+
+        let mut state = Vec::new();
+        let mut writer = SnapshotWriter::new(&mut state);
+        writer
+            .clock(Moment {
+                events: 4,
+                time: 30,
+            })
+            .unwrap();
+        writer.key(&EventKey::Id(String::from("abc"))).unwrap();
+        writer
+            .key(&EventKey::Row {
+                source: String::from("in, out.csv"),
+                row: 7,
+                content: u64::MAX,
+            })
+            .unwrap();
+        writer.account(1.into(), &account).unwrap();
+        writer.clearing(9, 1.into(), 2.into()).unwrap();
+        writer.finish().unwrap();
+
+        let snapshot = Snapshot::read(state.as_slice()).unwrap();
+        assert_eq!(
+            snapshot.clock,
+            Moment {
+                events: 4,
+                time: 30
+            }
+        );
+        assert_eq!(
+            snapshot.keys,
+            vec![
+                EventKey::Id(String::from("abc")),
+                EventKey::Row {
+                    source: String::from("in, out.csv"),
+                    row: 7,
+                    content: u64::MAX,
+                },
+            ]
+        );
+        assert_eq!(snapshot.clearing, vec![(9, 1.into(), 2.into())]);
+
+        let (client, restored) = &snapshot.accounts[0];
+        assert_eq!(*client, ClientId::from(1));
+        assert_eq!(restored.total_funds(), account.total_funds());
+        assert_eq!(restored.reserved_funds(), account.reserved_funds());
+        assert_eq!(restored.lock(), account.lock());
+        let mut deposits: Vec<_> = restored
+            .processed_deposits()
+            .map(|(transaction_id, deposit)| {
+                (
+                    u32::from(transaction_id),
+                    deposit.state(),
+                    deposit.was_disputed(),
+                )
+            })
+            .collect();
+        deposits.sort_unstable_by_key(|(transaction_id, _, _)| *transaction_id);
+        assert_eq!(
+            deposits,
+            vec![
+                (1, DepositState::MaybeSettled, false),
+                (2, DepositState::Disputed, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_snapshots_are_rejected() {
+        for (state, expected) in [
+            ("balance,1,2", "Unknown record \"balance\""),
+            ("clock,1,now", "Invalid time in clock record: \"now\""),
+            (
+                "deposit,1,1,10,settled,false",
+                "Record for client 1 before its account record",
+            ),
+            (
+                "account,1,0,0,0,0,,\naccount,1,0,0,0,0,,",
+                "More than one account record for client 1",
+            ),
+            (
+                "account,1,10,0,0,0,,\ndeposit,1,1,10,cleared,false",
+                "Invalid state in deposit record: \"cleared\"",
+            ),
+            (
+                "account,1,10,0,0,0,,\ndeposit,1,1,10,settled,false\ndeposit,1,1,10,settled,false",
+                "More than one record for transaction 1 of client 1",
+            ),
+            (
+                "account,1,0,0,0,0,admin,closed",
+                "Invalid lock_level in account record: \"closed\"",
+            ),
+            (
+                "account,1,0,0,0,0,admin,locked,,x",
+                "Invalid lock_sequence in account record: \"x\"",
+            ),
+        ] {
+            let error = Snapshot::read(state.as_bytes()).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        }
+
+        let state = "account,1,10,0,0,0,,\ndeposit,1,1,10,disputed,true";
+        assert!(matches!(
+            Snapshot::read(state.as_bytes()),
+            Err(SnapshotError::InconsistentAccount(_))
+        ));
+    }
+}
@@ -0,0 +1,350 @@
+use {
+    crate::{account::Account, ClientId},
+    derive_more::Display,
+    std::{
+        collections::{hash_map, HashMap},
+        iter, mem,
+        str::FromStr,
+    },
+    thiserror::Error,
+};
+
+/// Number of possible clients, one slot each in dense storage.
+const SLOTS: usize = 1 << 16;
+
+/// Number of accounts past which automatic storage switches from a map to a dense table. The
+/// table takes a fixed few megabytes whatever the number of accounts, which is only worth it once
+/// a good share of the client space is in use.
+const AUTO_DENSE_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Error)]
+#[error("Unknown account storage: \"{0}\"")]
+pub struct UnknownStorageKind(String);
+
+/// How an engine stores its accounts.
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    /// A hash map, which only takes memory for the clients seen.
+    #[display(fmt = "map")]
+    Map,
+    /// A table with a slot for every possible client, indexed directly by client ID. Accounts are
+    /// iterated in order of client ID.
+    #[display(fmt = "dense")]
+    Dense,
+    /// A map until there are enough accounts to make the dense table worth its memory.
+    #[default]
+    #[display(fmt = "auto")]
+    Auto,
+}
+
+impl FromStr for StorageKind {
+    type Err = UnknownStorageKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "map" => Ok(Self::Map),
+            "dense" => Ok(Self::Dense),
+            "auto" => Ok(Self::Auto),
+            unknown => Err(UnknownStorageKind(unknown.to_owned())),
+        }
+    }
+}
+
+/// The accounts of an engine, indexed by client.
+#[derive(Debug, Default)]
+pub struct AccountStore {
+    kind: StorageKind,
+    storage: Storage,
+}
+
+#[derive(Debug)]
+enum Storage {
+    Map(HashMap<ClientId, Account>),
+    Dense(DenseAccounts),
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::Map(HashMap::new())
+    }
+}
+
+impl AccountStore {
+    pub fn new(kind: StorageKind) -> Self {
+        let storage = match kind {
+            StorageKind::Map | StorageKind::Auto => Storage::default(),
+            StorageKind::Dense => Storage::Dense(DenseAccounts::new()),
+        };
+        Self { kind, storage }
+    }
+
+    pub fn kind(&self) -> StorageKind {
+        self.kind
+    }
+
+    /// Whether accounts are currently in a dense table, which automatic storage only switches to
+    /// once it holds enough of them.
+    pub fn is_dense(&self) -> bool {
+        matches!(self.storage, Storage::Dense(_))
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.storage {
+            Storage::Map(accounts) => accounts.len(),
+            Storage::Dense(accounts) => accounts.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&Account> {
+        match &self.storage {
+            Storage::Map(accounts) => accounts.get(&client),
+            Storage::Dense(accounts) => accounts.get(client),
+        }
+    }
+
+    pub fn get_mut(&mut self, client: ClientId) -> Option<&mut Account> {
+        match &mut self.storage {
+            Storage::Map(accounts) => accounts.get_mut(&client),
+            Storage::Dense(accounts) => accounts.get_mut(client),
+        }
+    }
+
+    /// Returns the account of a client, creating it first if needed, and whether it was created.
+    pub fn get_or_create(&mut self, client: ClientId) -> (&mut Account, bool) {
+        if self.kind == StorageKind::Auto && self.len() == AUTO_DENSE_THRESHOLD {
+            self.densify();
+        }
+        match &mut self.storage {
+            Storage::Map(accounts) => match accounts.entry(client) {
+                hash_map::Entry::Occupied(entry) => (entry.into_mut(), false),
+                hash_map::Entry::Vacant(entry) => (entry.insert(Account::new()), true),
+            },
+            Storage::Dense(accounts) => accounts.get_or_create(client),
+        }
+    }
+
+    pub fn remove(&mut self, client: ClientId) -> Option<Account> {
+        match &mut self.storage {
+            Storage::Map(accounts) => accounts.remove(&client),
+            Storage::Dense(accounts) => accounts.remove(client),
+        }
+    }
+
+    /// Every account, in order of client ID when dense and in no particular order otherwise.
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.storage {
+            Storage::Map(accounts) => Iter::Map(accounts.iter()),
+            Storage::Dense(accounts) => Iter::Dense(accounts.iter()),
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Account> {
+        self.iter().map(|(_, account)| account)
+    }
+
+    /// Moves every account to a new store of the given kind.
+    pub fn into_kind(mut self, kind: StorageKind) -> Self {
+        let mut store = Self::new(kind);
+        let clients: Vec<_> = self.iter().map(|(client, _)| client).collect();
+        for client in clients {
+            if let Some(account) = self.remove(client) {
+                *store.get_or_create(client).0 = account;
+            }
+        }
+        store
+    }
+
+    /// Moves every account to a dense table, unless they already are in one.
+    fn densify(&mut self) {
+        if let Storage::Map(accounts) = &mut self.storage {
+            let mut dense = DenseAccounts::new();
+            for (client, account) in accounts.drain() {
+                *dense.get_or_create(client).0 = account;
+            }
+            self.storage = Storage::Dense(dense);
+        }
+    }
+}
+
+/// A slot for every possible client, with a bitmap of the slots that hold an account. Empty slots
+/// hold a fresh account, which doesn't allocate.
+#[derive(Debug)]
+struct DenseAccounts {
+    slots: Box<[Account]>,
+    present: Box<[u64]>,
+    len: usize,
+}
+
+impl DenseAccounts {
+    fn new() -> Self {
+        Self {
+            slots: iter::repeat_with(Account::new).take(SLOTS).collect(),
+            present: vec![0; SLOTS / 64].into_boxed_slice(),
+            len: 0,
+        }
+    }
+
+    fn index(client: ClientId) -> usize {
+        usize::from(u16::from(client))
+    }
+
+    fn is_present(&self, index: usize) -> bool {
+        self.present[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn get(&self, client: ClientId) -> Option<&Account> {
+        let index = Self::index(client);
+        self.is_present(index).then(|| &self.slots[index])
+    }
+
+    fn get_mut(&mut self, client: ClientId) -> Option<&mut Account> {
+        let index = Self::index(client);
+        if !self.is_present(index) {
+            return None;
+        }
+        Some(&mut self.slots[index])
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> (&mut Account, bool) {
+        let index = Self::index(client);
+        let created = !self.is_present(index);
+        if created {
+            self.present[index / 64] |= 1 << (index % 64);
+            self.len += 1;
+        }
+        (&mut self.slots[index], created)
+    }
+
+    fn remove(&mut self, client: ClientId) -> Option<Account> {
+        let index = Self::index(client);
+        if !self.is_present(index) {
+            return None;
+        }
+        self.present[index / 64] &= !(1 << (index % 64));
+        self.len -= 1;
+        Some(mem::take(&mut self.slots[index]))
+    }
+
+    fn iter(&self) -> DenseIter<'_> {
+        DenseIter {
+            accounts: self,
+            word_index: 0,
+            word: self.present[0],
+        }
+    }
+}
+
+/// Iterator over the accounts of an `AccountStore`.
+pub enum Iter<'a> {
+    Map(hash_map::Iter<'a, ClientId, Account>),
+    Dense(DenseIter<'a>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (ClientId, &'a Account);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Map(accounts) => accounts.next().map(|(client, account)| (*client, account)),
+            Self::Dense(accounts) => accounts.next(),
+        }
+    }
+}
+
+/// Iterates the set bits of the presence bitmap, one word at a time.
+pub struct DenseIter<'a> {
+    accounts: &'a DenseAccounts,
+    word_index: usize,
+    /// The bits of the current word that haven't been visited yet.
+    word: u64,
+}
+
+impl<'a> Iterator for DenseIter<'a> {
+    type Item = (ClientId, &'a Account);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.word == 0 {
+            self.word_index += 1;
+            self.word = *self.accounts.present.get(self.word_index)?;
+        }
+        let index = self.word_index * 64 + self.word.trailing_zeros() as usize;
+        self.word &= self.word - 1;
+        Some((ClientId::from(index as u16), &self.accounts.slots[index]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rust_decimal_macros::dec};
+
+    fn deposit(store: &mut AccountStore, client: u16) -> bool {
+        let (account, created) = store.get_or_create(client.into());
+        if created {
+            account
+                .deposit(u32::from(client).into(), dec!(1).into())
+                .unwrap();
+        }
+        created
+    }
+
+    #[test]
+    fn storages_hold_the_same_accounts() {
+        let clients = [7, 0, u16::MAX, 64, 63, 7, 1000];
+        for kind in [StorageKind::Map, StorageKind::Dense, StorageKind::Auto] {
+            let mut store = AccountStore::new(kind);
+            let created: Vec<_> = clients
+                .iter()
+                .map(|client| deposit(&mut store, *client))
+                .collect();
+            assert_eq!(
+                created,
+                [true, true, true, true, true, false, true],
+                "{}",
+                kind
+            );
+            assert_eq!(store.len(), 6);
+
+            assert!(store.remove(64.into()).is_some());
+            assert!(store.remove(64.into()).is_none());
+            assert!(store.get(64.into()).is_none());
+            assert_eq!(store.len(), 5);
+            assert!(deposit(&mut store, 64));
+
+            let mut accounts: Vec<_> = store
+                .iter()
+                .map(|(client, account)| (u16::from(client), account.available_funds()))
+                .collect();
+            if kind != StorageKind::Dense {
+                accounts.sort_by_key(|(client, _)| *client);
+            }
+            assert_eq!(
+                accounts,
+                [0, 7, 63, 64, 1000, u16::MAX]
+                    .iter()
+                    .map(|client| (*client, dec!(1).into()))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn auto_storage_switches_to_dense() {
+        let mut store = AccountStore::new(StorageKind::Auto);
+        for client in (0..=AUTO_DENSE_THRESHOLD as u16).rev() {
+            assert!(!store.is_dense());
+            deposit(&mut store, client);
+        }
+
+        assert!(store.is_dense());
+        assert_eq!(store.len(), AUTO_DENSE_THRESHOLD + 1);
+        let clients: Vec<_> = store.iter().map(|(client, _)| u16::from(client)).collect();
+        assert_eq!(
+            clients,
+            (0..=AUTO_DENSE_THRESHOLD as u16).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use {
+    crate::{event::EventKind, Amount},
+    std::{
+        collections::{BTreeMap, HashMap},
+        io::{self, Write},
+        time::Duration,
+    },
+};
+
+/// Counters kept by the engine while processing events.
+#[derive(Debug, Default, Clone)]
+pub struct Statistics {
+    pub rows_read: u64,
+    /// Events skipped because they had already been processed.
+    pub duplicates: u64,
+    pub applied: HashMap<EventKind, u64>,
+    /// Rejected events, keyed by the name of the error variant that caused the rejection.
+    pub rejected: BTreeMap<&'static str, u64>,
+}
+
+impl Statistics {
+    pub fn applied(&self, kind: EventKind) -> u64 {
+        self.applied.get(&kind).copied().unwrap_or_default()
+    }
+
+    pub fn total_rejected(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+}
+
+/// Summary of a whole run, suitable for ingestion by dashboards.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub statistics: Statistics,
+    pub locked_accounts: u64,
+    pub total_funds: Amount,
+    pub held_funds: Amount,
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    /// Rows processed per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.statistics.rows_read as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Writes the summary as a single JSON object. Amounts are written as strings so that no
+    /// precision is lost to floating point conversion.
+    pub fn write_json(&self, mut writer: impl Write) -> Result<(), io::Error> {
+        write!(
+            writer,
+            r#"{{"rows_read":{},"duplicates_skipped":{},"#,
+            self.statistics.rows_read, self.statistics.duplicates
+        )?;
+
+        write!(writer, r#""events_applied":{{"#)?;
+        for (i, kind) in EventKind::ALL.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            write!(writer, r#""{}":{}"#, kind, self.statistics.applied(*kind))?;
+        }
+        write!(writer, "}},")?;
+
+        write!(writer, r#""events_rejected":{{"#)?;
+        for (i, (variant, count)) in self.statistics.rejected.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            write!(writer, r#""{}":{}"#, variant, count)?;
+        }
+        write!(writer, "}},")?;
+
+        writeln!(
+            writer,
+            r#""locked_accounts":{},"total_funds":"{}","held_funds":"{}","elapsed_seconds":{},"events_per_second":{}}}"#,
+            self.locked_accounts,
+            self.total_funds,
+            self.held_funds,
+            self.elapsed.as_secs_f64(),
+            self.throughput(),
+        )?;
+
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rust_decimal_macros::dec};
+
+    #[test]
+    fn json_summary() {
+        let mut statistics = Statistics {
+            rows_read: 4,
+            ..Default::default()
+        };
+        statistics.applied.insert(EventKind::Deposit, 2);
+        statistics.applied.insert(EventKind::Dispute, 1);
+        statistics.rejected.insert("Withdraw::InsufficientFunds", 1);
+
+        let summary = Summary {
+            statistics,
+            locked_accounts: 0,
+            total_funds: Amount::from(dec!(12.50)),
+            held_funds: Amount::from(dec!(2.5)),
+            elapsed: Duration::from_secs(2),
+        };
+
+        let expected = concat!(
+            r#"{"rows_read":4,"duplicates_skipped":0,"#,
+            r#""events_applied":{"deposit":2,"withdrawal":0,"dispute":1,"resolve":0,"chargeback":0,"#,
+            r#""withdrawal_request":0,"withdrawal_settle":0,"withdrawal_cancel":0,"clear":0},"#,
+            r#""events_rejected":{"Withdraw::InsufficientFunds":1},"#,
+            r#""locked_accounts":0,"total_funds":"12.50","held_funds":"2.5","#,
+            r#""elapsed_seconds":2,"events_per_second":2}"#,
+            "\n",
+        );
+        let mut actual = Vec::new();
+        summary.write_json(&mut actual).unwrap();
+
+        assert_eq!(expected, std::str::from_utf8(&actual).unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+use {
+    crate::Moment,
+    std::{collections::VecDeque, fmt, str::FromStr},
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+#[error("Invalid window: \"{0}\", expected events:N or seconds:N with N above zero")]
+pub struct InvalidWindow(String);
+
+/// Length of a rolling window, written as `events:N` or `seconds:N`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// The last N events handled by the engine, whichever clients they were for.
+    Events(u64),
+    /// The last N seconds, as measured by the input's timestamps.
+    Seconds(u64),
+}
+
+impl Window {
+    /// Whether something that happened at `then` is still in the window at `now`.
+    pub fn contains(self, then: Moment, now: Moment) -> bool {
+        match self {
+            Self::Events(events) => now.events < then.events.saturating_add(events),
+            Self::Seconds(seconds) => now.time < then.time.saturating_add(seconds),
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = InvalidWindow;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidWindow(s.to_owned());
+        let window = match s.split_once(':') {
+            Some(("events", n)) => n.parse().map(Self::Events).map_err(|_| invalid())?,
+            Some(("seconds", n)) => n.parse().map(Self::Seconds).map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        match window {
+            Self::Events(0) | Self::Seconds(0) => Err(invalid()),
+            window => Ok(window),
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Events(events) => write!(f, "events:{}", events),
+            Self::Seconds(seconds) => write!(f, "seconds:{}", seconds),
+        }
+    }
+}
+
+/// Values recorded over time, of which only those within a window count.
+///
+/// Values are only forgotten once they are out of the window at the horizon given to `prune`.
+/// The engine passes the start of the batch in progress, if any, so that a batch rolled back to
+/// its start finds every value that was in the window then.
+#[derive(Debug, Clone)]
+pub(crate) struct RollingWindow<T> {
+    window: Window,
+    /// Values that may still be in the window, oldest first.
+    entries: VecDeque<(Moment, T)>,
+    /// Number of values ever recorded, so that the latest ones can be undone.
+    recorded: u64,
+}
+
+impl<T> RollingWindow<T> {
+    pub(crate) fn new(window: Window) -> Self {
+        Self {
+            window,
+            entries: VecDeque::new(),
+            recorded: 0,
+        }
+    }
+
+    pub(crate) fn set_window(&mut self, window: Window) {
+        self.window = window;
+    }
+
+    pub(crate) fn push(&mut self, now: Moment, value: T) {
+        self.entries.push_back((now, value));
+        self.recorded += 1;
+    }
+
+    /// The values still in the window at `now`.
+    pub(crate) fn values(&self, now: Moment) -> impl Iterator<Item = &T> {
+        self.values_within(self.window, now)
+    }
+
+    /// The values within a `window` no longer than the one kept, at `now`.
+    pub(crate) fn values_within(&self, window: Window, now: Moment) -> impl Iterator<Item = &T> {
+        self.entries
+            .iter()
+            .filter(move |(then, _)| window.contains(*then, now))
+            .map(|(_, value)| value)
+    }
+
+    /// Every value kept, with when it was recorded, oldest first. Some may already be out of the
+    /// window.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Moment, &T)> {
+        self.entries.iter().map(|(then, value)| (*then, value))
+    }
+
+    /// Forgets the values that are out of the window at `horizon`, and so at any later moment.
+    pub(crate) fn prune(&mut self, horizon: Moment) {
+        while let Some((then, _)) = self.entries.front() {
+            if self.window.contains(*then, horizon) {
+                break;
+            }
+            self.entries.pop_front();
+        }
+    }
+
+    pub(crate) fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    /// Forgets the values recorded after the first `recorded`.
+    pub(crate) fn undo(&mut self, recorded: u64) {
+        while self.recorded > recorded {
+            self.entries.pop_back();
+            self.recorded -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_windows() {
+        for window in [Window::Events(3), Window::Seconds(86400)] {
+            assert_eq!(window.to_string().parse::<Window>().unwrap(), window);
+        }
+        for invalid in ["events", "events:0", "seconds:-1", "days:1"] {
+            assert!(invalid.parse::<Window>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn values_leave_the_window() {
+        let at = |events| Moment { events, time: 0 };
+        let mut window = RollingWindow::new(Window::Events(3));
+        for (events, value) in [(0, 10), (1, 20), (2, 40)] {
+            window.push(at(events), value);
+        }
+        assert_eq!(window.values(at(2)).sum::<i32>(), 70);
+        assert_eq!(window.values(at(4)).sum::<i32>(), 40);
+        assert_eq!(
+            window.values_within(Window::Events(1), at(2)).sum::<i32>(),
+            40
+        );
+
+        // Pruning only as of an earlier horizon keeps what is still in the window then.
+        window.prune(at(3));
+        assert_eq!(window.values(at(2)).sum::<i32>(), 60);
+        window.push(at(4), 5);
+        window.undo(3);
+        assert_eq!(window.values(at(4)).sum::<i32>(), 40);
+    }
+}
@@ -0,0 +1,378 @@
+//! Differential tests of the engine against a deliberately simple reference model.
+//!
+//! Proptest shrinks any failing sequence to a minimal one and records its seed in
+//! `tests/model.regressions`, which is replayed before any new cases on every run. Commit that
+//! file along with the fix so the case stays covered.
+
+use {
+    engine::{
+        engine::Engine,
+        event::{Event, EventData},
+        Amount, ClientId,
+    },
+    proptest::{prelude::*, test_runner::FileFailurePersistence},
+    rust_decimal::Decimal,
+    std::collections::BTreeMap,
+};
+
+/// Small ranges so that generated events often collide on clients and transactions.
+const CLIENTS: u16 = 4;
+const TRANSACTIONS: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DepositStatus {
+    Settled,
+    Disputed,
+    Reversed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WithdrawalStatus {
+    Reserved,
+    Settled,
+    Cancelled,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ClientState {
+    available: Decimal,
+    held: Decimal,
+    reserved: Decimal,
+    locked: bool,
+}
+
+/// The rules of the engine as a naive event-sourced fold: only the accepted events are stored,
+/// and every question about a client is answered by scanning all of them from the start.
+#[derive(Debug, Default)]
+struct Model {
+    accepted: Vec<Event>,
+    clients: Vec<ClientId>,
+}
+
+impl Model {
+    fn client_events(&self, client: ClientId) -> impl Iterator<Item = EventData> + '_ {
+        self.accepted
+            .iter()
+            .filter(move |event| event.client == client)
+            .map(|event| event.data)
+    }
+
+    fn deposit(&self, client: ClientId, tx: u32) -> Option<(Decimal, DepositStatus)> {
+        let mut deposit = None;
+        for data in self.client_events(client) {
+            match data {
+                EventData::Deposit {
+                    transaction_id,
+                    amount,
+                } if u32::from(transaction_id) == tx => {
+                    deposit = Some((amount.into(), DepositStatus::Settled))
+                }
+                EventData::Dispute { transaction_id } if u32::from(transaction_id) == tx => {
+                    deposit = deposit.map(|(amount, _)| (amount, DepositStatus::Disputed))
+                }
+                EventData::Resolve { transaction_id } if u32::from(transaction_id) == tx => {
+                    deposit = deposit.map(|(amount, _)| (amount, DepositStatus::Settled))
+                }
+                EventData::Chargeback { transaction_id } if u32::from(transaction_id) == tx => {
+                    deposit = deposit.map(|(amount, _)| (amount, DepositStatus::Reversed))
+                }
+                _ => {}
+            }
+        }
+        deposit
+    }
+
+    fn withdrawal(&self, client: ClientId, tx: u32) -> Option<(Decimal, WithdrawalStatus)> {
+        let mut withdrawal = None;
+        for data in self.client_events(client) {
+            match data {
+                EventData::WithdrawalRequest {
+                    transaction_id,
+                    amount,
+                } if u32::from(transaction_id) == tx => {
+                    withdrawal = Some((amount.into(), WithdrawalStatus::Reserved))
+                }
+                EventData::WithdrawalSettle { transaction_id }
+                    if u32::from(transaction_id) == tx =>
+                {
+                    withdrawal = withdrawal.map(|(amount, _)| (amount, WithdrawalStatus::Settled))
+                }
+                EventData::WithdrawalCancel { transaction_id }
+                    if u32::from(transaction_id) == tx =>
+                {
+                    withdrawal = withdrawal.map(|(amount, _)| (amount, WithdrawalStatus::Cancelled))
+                }
+                _ => {}
+            }
+        }
+        withdrawal
+    }
+
+    fn state(&self, client: ClientId) -> ClientState {
+        let mut state = ClientState::default();
+        for data in self.client_events(client) {
+            let amount = |tx| self.deposit(client, tx).unwrap().0;
+            match data {
+                EventData::Deposit { amount, .. } => state.available += Decimal::from(amount),
+                EventData::Withdrawal { amount, .. } => state.available -= Decimal::from(amount),
+                EventData::Dispute { transaction_id } => {
+                    let amount = amount(transaction_id.into());
+                    state.available -= amount;
+                    state.held += amount;
+                }
+                EventData::Resolve { transaction_id } => {
+                    let amount = amount(transaction_id.into());
+                    state.held -= amount;
+                    state.available += amount;
+                }
+                EventData::Chargeback { transaction_id } => {
+                    state.held -= amount(transaction_id.into());
+                    state.locked = true;
+                }
+                EventData::WithdrawalRequest { amount, .. } => {
+                    state.available -= Decimal::from(amount);
+                    state.reserved += Decimal::from(amount);
+                }
+                EventData::WithdrawalSettle { transaction_id } => {
+                    state.reserved -= self.withdrawal(client, transaction_id.into()).unwrap().0;
+                }
+                EventData::WithdrawalCancel { transaction_id } => {
+                    let amount = self.withdrawal(client, transaction_id.into()).unwrap().0;
+                    state.reserved -= amount;
+                    state.available += amount;
+                }
+                // Deposits are available immediately, so there is never anything to clear.
+                EventData::Clear { .. } => {}
+            }
+        }
+        state
+    }
+
+    /// Applies an event, returning the name of the error the engine should reject it with.
+    fn apply(&mut self, event: Event) -> Result<(), &'static str> {
+        if !self.clients.contains(&event.client) {
+            self.clients.push(event.client);
+        }
+        let state = self.state(event.client);
+        let deposit = self.deposit(event.client, event.data.transaction_id().into());
+        let status = deposit.map(|(_, status)| status);
+        let withdrawal = self
+            .withdrawal(event.client, event.data.transaction_id().into())
+            .map(|(_, status)| status);
+
+        let result = match event.data {
+            EventData::Deposit { .. } if state.locked => Err("Deposit::AccountLocked"),
+            EventData::Deposit { .. } if deposit.is_some() => {
+                Err("Deposit::DuplicateTransactionId")
+            }
+            EventData::Withdrawal { .. } if state.locked => Err("Withdraw::AccountLocked"),
+            EventData::Withdrawal { amount, .. } if state.available < amount.into() => {
+                Err("Withdraw::InsufficientFunds")
+            }
+            EventData::Dispute { .. } => match status {
+                None => Err("Dispute::DepositDoesNotExist"),
+                Some(DepositStatus::Disputed) => Err("Dispute::DepositAlreadyDisputed"),
+                Some(DepositStatus::Reversed) => Err("Dispute::DepositAlreadyReversed"),
+                Some(DepositStatus::Settled) => Ok(()),
+            },
+            EventData::Resolve { .. } => match status {
+                None => Err("Resolve::DepositDoesNotExist"),
+                Some(DepositStatus::Settled) => Err("Resolve::DepositNotDisputed"),
+                Some(DepositStatus::Reversed) => Err("Resolve::DepositAlreadyReversed"),
+                Some(DepositStatus::Disputed) => Ok(()),
+            },
+            EventData::Chargeback { .. } => match status {
+                None => Err("Chargeback::DepositDoesNotExist"),
+                Some(DepositStatus::Settled) => Err("Chargeback::DepositNotDisputed"),
+                Some(DepositStatus::Reversed) => Err("Chargeback::DepositAlreadyReversed"),
+                Some(DepositStatus::Disputed) => Ok(()),
+            },
+            EventData::WithdrawalRequest { .. } if state.locked => Err("Withdraw::AccountLocked"),
+            EventData::WithdrawalRequest { amount, .. } if state.available < amount.into() => {
+                Err("Withdraw::InsufficientFunds")
+            }
+            EventData::WithdrawalRequest { .. } if withdrawal.is_some() => {
+                Err("Withdraw::DuplicateTransactionId")
+            }
+            EventData::WithdrawalSettle { .. } => match withdrawal {
+                None => Err("WithdrawalState::WithdrawalDoesNotExist"),
+                Some(WithdrawalStatus::Settled) => Err("WithdrawalState::WithdrawalAlreadySettled"),
+                Some(WithdrawalStatus::Cancelled) => {
+                    Err("WithdrawalState::WithdrawalAlreadyCancelled")
+                }
+                Some(WithdrawalStatus::Reserved) => Ok(()),
+            },
+            EventData::WithdrawalCancel { .. } => match withdrawal {
+                None => Err("WithdrawalState::WithdrawalDoesNotExist"),
+                Some(WithdrawalStatus::Settled) => Err("WithdrawalState::WithdrawalAlreadySettled"),
+                Some(WithdrawalStatus::Cancelled) => {
+                    Err("WithdrawalState::WithdrawalAlreadyCancelled")
+                }
+                Some(WithdrawalStatus::Reserved) => Ok(()),
+            },
+            EventData::Clear { .. } => match status {
+                None => Err("Clear::DepositDoesNotExist"),
+                Some(_) => Err("Clear::DepositAlreadyCleared"),
+            },
+            _ => Ok(()),
+        };
+
+        if result.is_ok() {
+            self.accepted.push(event);
+        }
+        result
+    }
+
+    fn states(&self) -> BTreeMap<ClientId, ClientState> {
+        self.clients
+            .iter()
+            .map(|client| (*client, self.state(*client)))
+            .collect()
+    }
+}
+
+fn engine_states(engine: &Engine) -> BTreeMap<ClientId, ClientState> {
+    engine
+        .accounts()
+        .map(|(client, account)| {
+            let state = ClientState {
+                available: account.available_funds().into(),
+                held: account.held_funds().into(),
+                reserved: account.reserved_funds().into(),
+                locked: account.is_locked(),
+            };
+            (client, state)
+        })
+        .collect()
+}
+
+fn amount() -> impl Strategy<Value = Amount> {
+    (0..100_000i64).prop_map(|units| Decimal::new(units, 4).into())
+}
+
+fn event() -> impl Strategy<Value = Event> {
+    let data = (1..=TRANSACTIONS).prop_flat_map(|tx| {
+        let transaction_id = tx.into();
+        prop_oneof![
+            3 => amount().prop_map(move |amount| EventData::Deposit { transaction_id, amount }),
+            2 => amount().prop_map(move |amount| EventData::Withdrawal { transaction_id, amount }),
+            2 => Just(EventData::Dispute { transaction_id }),
+            1 => Just(EventData::Resolve { transaction_id }),
+            1 => Just(EventData::Chargeback { transaction_id }),
+            1 => amount().prop_map(move |amount| EventData::WithdrawalRequest {
+                transaction_id,
+                amount,
+            }),
+            1 => Just(EventData::WithdrawalSettle { transaction_id }),
+            1 => Just(EventData::WithdrawalCancel { transaction_id }),
+            1 => Just(EventData::Clear { transaction_id }),
+        ]
+    });
+    (1..=CLIENTS, data).prop_map(|(client, data)| Event {
+        client: client.into(),
+        data,
+    })
+}
+
+/// A row of CSV input, which is either a valid event or a row the parser must reject.
+#[derive(Debug, Clone)]
+enum Row {
+    Valid(Event),
+    Malformed(&'static str),
+}
+
+impl Row {
+    fn to_csv(&self) -> String {
+        match self {
+            Self::Valid(Event { client, data }) => match data {
+                EventData::Deposit {
+                    transaction_id,
+                    amount,
+                } => format!("deposit,{},{},{}", client, transaction_id, amount),
+                EventData::Withdrawal {
+                    transaction_id,
+                    amount,
+                } => format!("withdrawal,{},{},{}", client, transaction_id, amount),
+                EventData::WithdrawalRequest {
+                    transaction_id,
+                    amount,
+                } => format!(
+                    "withdrawal_request,{},{},{}",
+                    client, transaction_id, amount
+                ),
+                _ => format!("{},{},{}", data.kind(), client, data.transaction_id()),
+            },
+            Self::Malformed(row) => String::from(*row),
+        }
+    }
+}
+
+fn row() -> impl Strategy<Value = Row> {
+    prop_oneof![
+        20 => event().prop_map(Row::Valid),
+        1 => prop::sample::select(vec![
+            "refund,1,1",
+            "deposit,1,1",
+            "deposit,1,1,ten",
+            "withdrawal,one,1,1",
+            "dispute,1,-1",
+            "deposit,70000,1,1",
+        ])
+        .prop_map(Row::Malformed),
+    ]
+}
+
+fn config() -> ProptestConfig {
+    ProptestConfig {
+        failure_persistence: Some(Box::new(FileFailurePersistence::WithSource("regressions"))),
+        ..ProptestConfig::default()
+    }
+}
+
+proptest! {
+    #![proptest_config(config())]
+
+    #[test]
+    fn engine_matches_model(events in prop::collection::vec(event(), 0..100)) {
+        let mut engine = Engine::new().with_audit();
+        let mut model = Model::default();
+
+        for event in events {
+            let expected = model.apply(event);
+            let actual = engine.handle_event(event).map_err(|e| e.variant());
+            prop_assert_eq!(actual, expected, "{:?}", event);
+        }
+
+        prop_assert_eq!(engine_states(&engine), model.states());
+        prop_assert!(engine.audit().is_ok());
+    }
+
+    #[test]
+    fn malformed_row_stops_processing(rows in prop::collection::vec(row(), 0..100)) {
+        let mut input = String::from("type,client,tx,amount\n");
+        for row in &rows {
+            input.push_str(&row.to_csv());
+            input.push('\n');
+        }
+
+        let mut engine = Engine::new();
+        let result = engine.read_events(input.as_bytes());
+
+        let mut model = Model::default();
+        let mut malformed = false;
+        for row in &rows {
+            match row {
+                Row::Valid(event) => {
+                    let _ = model.apply(*event);
+                }
+                Row::Malformed(_) => {
+                    malformed = true;
+                    break;
+                }
+            }
+        }
+
+        prop_assert_eq!(result.is_err(), malformed);
+        prop_assert_eq!(engine_states(&engine), model.states());
+    }
+}